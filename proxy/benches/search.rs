@@ -0,0 +1,75 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use proxy::search::{search, SearchInput, SearchSettings};
+use proxy::store::DocumentStore;
+use serde_json::{json, Value};
+
+const MATCH_ALL: &str = r#"{"sort":[{"_score":{"order":"desc"}}],"size":500,"version":true,"stored_fields":["*"],"script_fields":{},"docvalue_fields":[],"_source":{"excludes":[]},"query":{"bool":{"must":[],"filter":[{"match_all":{}}],"should":[],"must_not":[]}}}"#;
+const MULTI_MATCH: &str = r#"{"sort":[{"_score":{"order":"desc"}}],"size":500,"version":true,"stored_fields":["*"],"script_fields":{},"docvalue_fields":[],"_source":{"excludes":[]},"query":{"bool":{"must":[],"filter":[{"multi_match":{"lenient":true,"query":"it chance","type":"best_fields"}}],"should":[],"must_not":[]}}}"#;
+
+/// Corpus of `size` documents, built by repeating the default one.
+fn corpus(size: usize) -> Vec<Value> {
+    DocumentStore::default()
+        .documents()
+        .iter()
+        .cycle()
+        .take(size)
+        .cloned()
+        .collect()
+}
+
+/// Corpus of `size` distinct documents, each word occurring in a few of them only
+fn synthetic_corpus(size: usize) -> Vec<Value> {
+    (0..size)
+        .map(|i| {
+            json!({
+                "Title": format!("title{}", i % 1000),
+                "Description": format!("word{} word{} common", i % 97, i % 1013),
+            })
+        })
+        .collect()
+}
+
+fn bench_search(c: &mut Criterion) {
+    let settings = SearchSettings::default();
+    let mut group = c.benchmark_group("search");
+    for size in [3, 1_000, 10_000] {
+        let corpus = DocumentStore::new(corpus(size));
+        for (name, body) in [("match_all", MATCH_ALL), ("multi_match", MULTI_MATCH)] {
+            let input = SearchInput {
+                index: Some("my-first-index*"),
+                query: "ignore_unavailable=true&track_total_hits=true&timeout=30000ms",
+                body: body.as_bytes(),
+            };
+            group.bench_with_input(BenchmarkId::new(name, size), &input, |b, input| {
+                b.iter(|| search(&corpus, input, &settings).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Text queries on the inverted index compared to scanning all the documents
+fn bench_inverted_index(c: &mut Criterion) {
+    const SELECTIVE_MATCH: &str = r#"{"query":{"bool":{"filter":[{"match":{"Description":"word42"}},{"multi_match":{"query":"title7 title8"}}]}}}"#;
+    let settings = SearchSettings::default();
+    let mut group = c.benchmark_group("inverted_index");
+    for size in [5_000] {
+        for (name, store) in [
+            ("indexed", DocumentStore::new(synthetic_corpus(size))),
+            ("scan", DocumentStore::unindexed(synthetic_corpus(size))),
+        ] {
+            let input = SearchInput {
+                index: None,
+                query: "",
+                body: SELECTIVE_MATCH.as_bytes(),
+            };
+            group.bench_with_input(BenchmarkId::new(name, size), &input, |b, input| {
+                b.iter(|| search(&store, input, &settings).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search, bench_inverted_index);
+criterion_main!(benches);
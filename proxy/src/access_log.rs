@@ -0,0 +1,167 @@
+//! Access log in the JSON lines format, one object per request, for the operators:
+//! separate from the tracing logs, which are for debugging.
+
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Line of the access log
+#[derive(Serialize)]
+pub struct AccessLogEntry<'a> {
+    /// Time the request was received, RFC 3339 in UTC
+    pub timestamp: String,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub index: Option<&'a str>,
+    /// emulated, forwarded, rejected or blocked
+    pub outcome: &'a str,
+    pub status: u16,
+    pub duration_ms: f64,
+    /// None for streamed responses of unknown length
+    pub bytes_out: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x_opaque_id: Option<&'a str>,
+}
+
+/// Writer of the access log. The lines are handed over to a thread writing them,
+/// so a slow disk or stdout never stalls the requests.
+pub struct AccessLog {
+    lines: Sender<String>,
+}
+
+impl AccessLog {
+    /// Append to the file at the path, or write to stdout for `-`
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let output: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("can't open access log {}: {}", path.display(), err))?;
+            Box::new(file)
+        };
+        let (lines, received) = channel::<String>();
+        std::thread::spawn(move || {
+            let mut output = BufWriter::new(output);
+            // Flushed whenever there's nothing more to write, lines come in bursts
+            while let Ok(line) = received.recv() {
+                let _ = writeln!(output, "{}", line);
+                while let Ok(line) = received.try_recv() {
+                    let _ = writeln!(output, "{}", line);
+                }
+                let _ = output.flush();
+            }
+        });
+        Ok(AccessLog { lines })
+    }
+
+    pub fn log(&self, entry: &AccessLogEntry) {
+        if let Ok(line) = serde_json::to_string(entry) {
+            // The writing thread only stops with the process
+            let _ = self.lines.send(line);
+        }
+    }
+}
+
+/// Index of the request path, its first segment unless it's an API like `_search`
+pub fn path_index(path: &str) -> Option<&str> {
+    let segment = path.trim_start_matches('/').split('/').next()?;
+    (!segment.is_empty() && !segment.starts_with('_')).then_some(segment)
+}
+
+/// Time formatted like 2024-05-01T12:34:56.789Z
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // Civil date of the days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry<'a>(
+        method: &'a str,
+        path: &'a str,
+        x_opaque_id: Option<&'a str>,
+    ) -> AccessLogEntry<'a> {
+        AccessLogEntry {
+            timestamp: rfc3339(SystemTime::now()),
+            method,
+            path,
+            index: path_index(path),
+            outcome: "emulated",
+            status: 200,
+            duration_ms: 0.25,
+            bytes_out: Some(42),
+            x_opaque_id,
+        }
+    }
+
+    #[test]
+    fn one_json_line_per_request() {
+        let path =
+            std::env::temp_dir().join(format!("tinyq-access-log-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AccessLog::open(&path).unwrap();
+        log.log(&entry("POST", "/songs/_search", Some("dashboard-1")));
+        log.log(&entry("GET", "/_cat/indices", None));
+        // Written by the thread in the background
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["index"], "songs");
+        assert_eq!(lines[0]["x_opaque_id"], "dashboard-1");
+        assert_eq!(lines[1]["index"], serde_json::Value::Null);
+        assert!(lines[1].get("x_opaque_id").is_none());
+        assert_eq!(lines[1]["status"], 200);
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_714_566_896_789);
+        assert_eq!(rfc3339(time), "2024-05-01T12:34:56.789Z");
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}
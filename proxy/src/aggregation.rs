@@ -0,0 +1,97 @@
+//! Aggregations over the matching documents. Only the ones which don't need
+//! the field mappings are emulated, like counting the values of a field.
+
+use crate::alias::FieldAliases;
+use serde_json::{json, Value};
+
+/// Single parsed aggregation, without sub-aggregations
+#[derive(Debug)]
+pub enum Aggregation {
+    /// Number of values of the field, each element counting for array fields
+    ValueCount { field: String },
+}
+
+impl Aggregation {
+    /// Type of the aggregation, prefixing its name in the response with `typed_keys`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Aggregation::ValueCount { .. } => "value_count",
+        }
+    }
+
+    pub fn resolve_aliases(&mut self, aliases: &FieldAliases) {
+        match self {
+            Aggregation::ValueCount { field } => aliases.resolve(field),
+        }
+    }
+
+    /// Result of the aggregation over the documents
+    pub fn compute<'a>(&self, documents: impl Iterator<Item = &'a Value>) -> Value {
+        match self {
+            Aggregation::ValueCount { field } => {
+                let count: usize = documents
+                    .map(|source| match source.get(field) {
+                        None | Some(Value::Null) => 0,
+                        Some(Value::Array(values)) => {
+                            values.iter().filter(|value| !value.is_null()).count()
+                        }
+                        Some(_) => 1,
+                    })
+                    .sum();
+                json!({ "value": count })
+            }
+        }
+    }
+}
+
+// Parse `aggs` like {"descriptions": {"value_count": {"field": "Description"}}}
+pub fn parse_aggregations(value: &Value) -> Result<Vec<(String, Aggregation)>, String> {
+    let Value::Object(aggregations) = value else {
+        return Err(format!("unimplemented aggs value: {}", value));
+    };
+    let mut parsed = Vec::new();
+    for (name, aggregation) in aggregations {
+        let Some((aggregation_type, params)) = aggregation
+            .as_object()
+            .filter(|aggregation| aggregation.len() == 1)
+            .and_then(|aggregation| aggregation.iter().next())
+        else {
+            // Sub-aggregations and `meta` come next to the aggregation type
+            return Err(format!(
+                "unimplemented aggregation - expected single type: {}",
+                aggregation
+            ));
+        };
+        let aggregation = match aggregation_type.as_str() {
+            "value_count" => {
+                let Some(params) = params.as_object() else {
+                    return Err(format!("unimplemented value_count value: {}", params));
+                };
+                let mut field = None;
+                for (param_key, param_value) in params {
+                    match (param_key.as_str(), param_value) {
+                        ("field", Value::String(param_value)) => field = Some(param_value.clone()),
+                        _ => {
+                            return Err(format!(
+                                "unimplemented value_count parameter: {}",
+                                param_key
+                            ))
+                        }
+                    }
+                }
+                let Some(field) = field else {
+                    return Err(format!("unimplemented value_count without field: {}", name));
+                };
+                Aggregation::ValueCount { field }
+            }
+            _ => {
+                return Err(format!(
+                    "unimplemented aggregation type: {}",
+                    aggregation_type
+                ))
+            }
+        };
+        parsed.push((name.clone(), aggregation));
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,65 @@
+//! Aliases of the field names, for deployments where clients query fields under
+//! other names than the ones of the stored documents.
+
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Queried field name → stored field name. Shared, so the settings stay cheap to copy.
+#[derive(Clone, Debug, Default)]
+pub struct FieldAliases(Arc<HashMap<String, String>>);
+
+impl FieldAliases {
+    /// Parse a JSON object like {"message": "Description"}
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let Ok(Value::Object(aliases)) = serde_json::from_str(json) else {
+            return Err(format!(
+                "expected a JSON object of field aliases, got {}",
+                json
+            ));
+        };
+        let aliases = aliases
+            .into_iter()
+            .map(|(alias, field)| match field {
+                Value::String(field) => Ok((alias, field)),
+                _ => Err(format!(
+                    "expected a field name for alias {}, got {}",
+                    alias, field
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FieldAliases(Arc::new(aliases)))
+    }
+
+    /// Replace the field name with the stored one, if it's an alias
+    pub(crate) fn resolve(&self, field: &mut String) {
+        if let Some(stored) = self.0.get(field.as_str()) {
+            field.clone_from(stored);
+        }
+    }
+}
+
+impl Serialize for FieldAliases {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_resolve_to_the_stored_fields() {
+        let aliases = FieldAliases::parse(r#"{"message": "Description"}"#).unwrap();
+        let mut field = "message".to_string();
+        aliases.resolve(&mut field);
+        assert_eq!(field, "Description");
+        let mut field = "Title".to_string();
+        aliases.resolve(&mut field);
+        assert_eq!(field, "Title");
+        assert!(FieldAliases::parse(r#"{"message": 1}"#).is_err());
+        assert!(FieldAliases::parse(r#"["message"]"#).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! Text analysis, a simplified version of the OpenSearch standard analyzer.
+
+use std::borrow::Cow;
+
+/// Split text into word tokens on whitespace and punctuation.
+///
+/// Apostrophes inside words are kept (`I'd` stays one token), the ones at the
+/// start or end of a word are dropped. Empty tokens are never returned.
+/// Case is preserved, lowercasing is up to the caller.
+pub fn tokenize(text: &str) -> Vec<String> {
+    token_spans(text)
+        .map(|(start, end)| text[start..end].to_string())
+        .collect()
+}
+
+/// Byte ranges of the tokens of `tokenize` within the text, in order
+pub fn token_spans(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || is_apostrophe(c)))
+        .filter_map(move |word| {
+            let token = word.trim_matches(is_apostrophe);
+            if token.is_empty() {
+                return None;
+            }
+            // Both are substrings of the text, so the pointers give their offsets
+            let start = token.as_ptr() as usize - text.as_ptr() as usize;
+            Some((start, start + token.len()))
+        })
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '\u{2019}'
+}
+
+/// Tokens of the text as compared by the text clauses: lowercased unless matching is case-sensitive
+pub fn analyze(text: &str, case_sensitive: bool) -> Vec<String> {
+    tokenize(text)
+        .into_iter()
+        .map(|token| normalize(&token, case_sensitive).into_owned())
+        .collect()
+}
+
+pub fn normalize(text: &str, case_sensitive: bool) -> Cow<'_, str> {
+    if case_sensitive {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(text.to_lowercase())
+    }
+}
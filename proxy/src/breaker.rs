@@ -0,0 +1,189 @@
+//! Circuit breaker around OpenSearch: after repeated failures to reach it, forwarded
+//! requests are answered right away for a while instead of piling up on it.
+
+use serde::{Serialize, Serializer};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Thresholds of the breaker, from the config
+#[derive(Clone, Copy)]
+pub struct BreakerSettings {
+    /// Failures within `window` opening the breaker, 0 never opens it
+    pub threshold: u32,
+    pub window: Duration,
+    /// How long the breaker stays open before letting a trial request through
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct CircuitBreaker(Mutex<State>);
+
+#[derive(Debug)]
+enum State {
+    /// Requests are forwarded, the recent failures are kept
+    Closed { failures: VecDeque<Instant> },
+    /// Requests are short-circuited until the cooldown is over
+    Open { until: Instant },
+    /// A trial request was let through, the others are short-circuited until it's done
+    HalfOpen { trial_started: Instant },
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Closed {
+            failures: VecDeque::new(),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Whether a request can be forwarded now. Once the cooldown is over,
+    /// a single trial request is let through to probe whether OpenSearch recovered.
+    pub fn allow(&self, settings: BreakerSettings) -> bool {
+        let now = Instant::now();
+        let mut state = self.0.lock().unwrap();
+        let trial = match &*state {
+            State::Closed { .. } => return true,
+            State::Open { until } => now >= *until,
+            // A trial never reporting back, like a cancelled request, doesn't block forever
+            State::HalfOpen { trial_started } => now >= *trial_started + settings.cooldown,
+        };
+        if trial {
+            *state = State::HalfOpen { trial_started: now };
+        }
+        trial
+    }
+
+    /// OpenSearch answered, whatever the status: the breaker closes
+    pub fn record_success(&self) {
+        *self.0.lock().unwrap() = State::default();
+    }
+
+    /// OpenSearch couldn't be reached or timed out
+    pub fn record_failure(&self, settings: BreakerSettings) {
+        let now = Instant::now();
+        let mut state = self.0.lock().unwrap();
+        let open = match &mut *state {
+            State::Closed { .. } if settings.threshold == 0 => false,
+            State::Closed { failures } => {
+                failures.push_back(now);
+                while failures
+                    .front()
+                    .is_some_and(|failure| now.duration_since(*failure) > settings.window)
+                {
+                    failures.pop_front();
+                }
+                failures.len() >= settings.threshold as usize
+            }
+            // The trial failed, OpenSearch still isn't back
+            State::HalfOpen { .. } => true,
+            // Request forwarded before the breaker opened, the cooldown stays the same
+            State::Open { .. } => false,
+        };
+        if open {
+            *state = State::Open {
+                until: now + settings.cooldown,
+            };
+        }
+    }
+}
+
+/// State of the breaker: closed, open or half_open
+impl Serialize for CircuitBreaker {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let state = match *self.0.lock().unwrap() {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        };
+        serializer.serialize_str(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::thread::sleep;
+
+    const COOLDOWN: Duration = Duration::from_millis(20);
+
+    fn settings(threshold: u32) -> BreakerSettings {
+        BreakerSettings {
+            threshold,
+            window: Duration::from_secs(10),
+            cooldown: COOLDOWN,
+        }
+    }
+
+    fn state(breaker: &CircuitBreaker) -> Value {
+        serde_json::to_value(breaker).unwrap()
+    }
+
+    #[test]
+    fn opens_after_threshold_failures_and_closes_on_recovery() {
+        let breaker = CircuitBreaker::default();
+        let settings = settings(2);
+        breaker.record_failure(settings);
+        assert!(breaker.allow(settings));
+        breaker.record_failure(settings);
+        assert_eq!(state(&breaker), "open");
+        assert!(!breaker.allow(settings));
+
+        sleep(COOLDOWN);
+        // A single trial is let through
+        assert!(breaker.allow(settings));
+        assert_eq!(state(&breaker), "half_open");
+        assert!(!breaker.allow(settings));
+        breaker.record_success();
+        assert_eq!(state(&breaker), "closed");
+        assert!(breaker.allow(settings));
+    }
+
+    #[test]
+    fn failed_trial_opens_again() {
+        let breaker = CircuitBreaker::default();
+        let settings = settings(1);
+        breaker.record_failure(settings);
+        sleep(COOLDOWN);
+        assert!(breaker.allow(settings));
+        breaker.record_failure(settings);
+        assert_eq!(state(&breaker), "open");
+        assert!(!breaker.allow(settings));
+    }
+
+    #[test]
+    fn trial_never_reporting_back_is_replaced_after_the_cooldown() {
+        let breaker = CircuitBreaker::default();
+        let settings = settings(1);
+        breaker.record_failure(settings);
+        sleep(COOLDOWN);
+        assert!(breaker.allow(settings));
+        assert!(!breaker.allow(settings));
+        sleep(COOLDOWN);
+        assert!(breaker.allow(settings));
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let breaker = CircuitBreaker::default();
+        let settings = BreakerSettings {
+            window: Duration::from_millis(10),
+            ..settings(2)
+        };
+        breaker.record_failure(settings);
+        sleep(Duration::from_millis(20));
+        breaker.record_failure(settings);
+        assert_eq!(state(&breaker), "closed");
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::default();
+        for _ in 0..10 {
+            breaker.record_failure(settings(0));
+        }
+        assert!(breaker.allow(settings(0)));
+    }
+}
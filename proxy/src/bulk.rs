@@ -0,0 +1,70 @@
+//! Recognizing the operations of forwarded `_bulk` requests, only to count them:
+//! the body is always forwarded as it is.
+
+use serde_json::Value;
+
+/// Number of operations of a `_bulk` body, per action
+#[derive(Debug, Default, PartialEq)]
+pub struct BulkActions {
+    pub index: u64,
+    pub create: u64,
+    pub update: u64,
+    pub delete: u64,
+}
+
+/// Count the operations of a `_bulk` ndjson body: action lines like {"index": {...}},
+/// each followed by a document line except for delete.
+/// None when the body isn't valid ndjson or has unknown actions, OpenSearch rejects it then.
+pub fn count_bulk_actions(body: &[u8]) -> Option<BulkActions> {
+    let mut actions = BulkActions::default();
+    let mut lines = body
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace));
+    while let Some(line) = lines.next() {
+        let Ok(Value::Object(action)) = serde_json::from_slice(line) else {
+            return None;
+        };
+        let mut action = action.keys();
+        let (Some(action), None) = (action.next(), action.next()) else {
+            return None;
+        };
+        let (counter, has_document) = match action.as_str() {
+            "index" => (&mut actions.index, true),
+            "create" => (&mut actions.create, true),
+            "update" => (&mut actions.update, true),
+            "delete" => (&mut actions.delete, false),
+            _ => return None,
+        };
+        if has_document {
+            let document = lines.next()?;
+            serde_json::from_slice::<Value>(document).ok()?;
+        }
+        *counter += 1;
+    }
+    Some(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_are_counted_per_action() {
+        let body =
+            b"{\"index\": {\"_index\": \"songs\", \"_id\": \"1\"}}\n{\"Description\": \"fire\"}\n\
+            {\"delete\": {\"_index\": \"songs\", \"_id\": \"2\"}}\n";
+        let expected = BulkActions {
+            index: 1,
+            delete: 1,
+            ..BulkActions::default()
+        };
+        assert_eq!(count_bulk_actions(body), Some(expected));
+    }
+
+    #[test]
+    fn malformed_bulk_is_not_counted() {
+        assert_eq!(count_bulk_actions(b"{\"index\": {}}\n{not json\n"), None);
+        assert_eq!(count_bulk_actions(b"{\"index\": {}}\n"), None);
+        assert_eq!(count_bulk_actions(b"{\"upsert\": {}}\n{}\n"), None);
+    }
+}
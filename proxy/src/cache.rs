@@ -0,0 +1,101 @@
+//! Cache of the emulated search responses: dashboards send the same queries over and over.
+
+use hyper::body::Bytes;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Least recently used responses (before compression) of the emulated searches,
+/// up to a number of entries. Results of a previous document store are never served.
+pub struct SearchCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    // Generation of the document store the responses were computed from
+    generation: u64,
+    responses: HashMap<String, (Bytes, u64)>,
+    // Bumped on every use of an entry, the smallest is the least recently used
+    clock: u64,
+}
+
+impl SearchCache {
+    /// Cache of up to `capacity` responses, 0 disables it
+    pub fn new(capacity: usize) -> Self {
+        SearchCache {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, generation: u64, key: &str) -> Option<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            return None;
+        }
+        entries.clock += 1;
+        let clock = entries.clock;
+        let (response, last_used) = entries.responses.get_mut(key)?;
+        *last_used = clock;
+        Some(response.clone())
+    }
+
+    pub fn insert(&self, generation: u64, key: String, response: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            entries.generation = generation;
+            entries.responses.clear();
+        }
+        if entries.responses.len() >= self.capacity && !entries.responses.contains_key(&key) {
+            // Linear, but only when full and only over the bounded entries
+            let least_recently_used = entries
+                .responses
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recently_used) = least_recently_used {
+                entries.responses.remove(&least_recently_used);
+            }
+        }
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.responses.insert(key, (response, clock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_response_is_evicted() {
+        let cache = SearchCache::new(2);
+        cache.insert(1, "a".to_string(), Bytes::from("A"));
+        cache.insert(1, "b".to_string(), Bytes::from("B"));
+        assert_eq!(cache.get(1, "a"), Some(Bytes::from("A")));
+        cache.insert(1, "c".to_string(), Bytes::from("C"));
+        assert_eq!(cache.get(1, "b"), None);
+        assert_eq!(cache.get(1, "a"), Some(Bytes::from("A")));
+        assert_eq!(cache.get(1, "c"), Some(Bytes::from("C")));
+    }
+
+    #[test]
+    fn responses_of_another_store_are_never_served() {
+        let cache = SearchCache::new(2);
+        cache.insert(1, "a".to_string(), Bytes::from("A"));
+        assert_eq!(cache.get(2, "a"), None);
+        cache.insert(2, "b".to_string(), Bytes::from("B"));
+        assert_eq!(cache.get(1, "a"), None);
+    }
+
+    #[test]
+    fn disabled_cache_keeps_nothing() {
+        let cache = SearchCache::new(0);
+        cache.insert(1, "a".to_string(), Bytes::from("A"));
+        assert_eq!(cache.get(1, "a"), None);
+    }
+}
@@ -0,0 +1,198 @@
+//! Emulation of the `_cat` APIs, listing what the document store contains.
+
+use crate::search::split_options;
+use crate::store::DocumentStore;
+use serde_json::{Map, Value};
+
+/// Response of a _cat API, either an aligned text table or JSON
+pub struct CatResponse {
+    pub body: String,
+    pub content_type: &'static str,
+}
+
+/// Columns of `_cat/indices`, in the OpenSearch order
+const INDICES_COLUMNS: &[&str] = &[
+    "health",
+    "status",
+    "index",
+    "uuid",
+    "pri",
+    "rep",
+    "docs.count",
+    "docs.deleted",
+    "store.size",
+    "pri.store.size",
+];
+
+/// Columns aligned to the right in the text table, like OpenSearch does for numbers
+const INDICES_NUMERIC_COLUMNS: &[&str] = &["pri", "rep", "docs.count", "docs.deleted"];
+
+/// Try to handle a _cat/indices request, with one row per index of the store.
+/// The emulated indices are always healthy: a single primary shard without replicas.
+/// Stores without indices (no `_index` field in the documents) aren't handled.
+pub fn cat_indices(store: &DocumentStore, query: &str) -> Result<CatResponse, String> {
+    let mut verbose = false;
+    let mut json = false;
+    for option in split_options(query) {
+        match (
+            option.first().map(String::as_str),
+            option.get(1).map(String::as_str),
+        ) {
+            (Some("v"), None | Some("true")) => verbose = true,
+            (Some("v"), Some("false")) => verbose = false,
+            (Some("format"), Some("json")) => json = true,
+            (Some("format"), Some("text")) => json = false,
+            (Some(key), Some(value)) => {
+                return Err(format!("unimplemented _cat option: {}={}", key, value))
+            }
+            (Some(key), None) => return Err(format!("unimplemented _cat option: {}", key)),
+            (None, _) => {}
+        }
+    }
+
+    let indices = store.indices();
+    if indices.is_empty() {
+        return Err("no index metadata in the documents".to_string());
+    }
+    let rows: Vec<Vec<String>> = indices
+        .iter()
+        .map(|index| {
+            let size = format_size(index.size_in_bytes);
+            vec![
+                "green".to_string(),
+                "open".to_string(),
+                index.name.clone(),
+                // What OpenSearch reports for unknown UUIDs
+                "_na_".to_string(),
+                "1".to_string(),
+                "0".to_string(),
+                index.docs_count.to_string(),
+                "0".to_string(),
+                size.clone(),
+                size,
+            ]
+        })
+        .collect();
+
+    if json {
+        // All values are strings in the JSON format too
+        let rows: Vec<Value> = rows
+            .into_iter()
+            .map(|row| {
+                let row: Map<String, Value> = INDICES_COLUMNS
+                    .iter()
+                    .map(|column| column.to_string())
+                    .zip(row.into_iter().map(Value::String))
+                    .collect();
+                Value::Object(row)
+            })
+            .collect();
+        return Ok(CatResponse {
+            body: Value::Array(rows).to_string(),
+            content_type: "application/json; charset=UTF-8",
+        });
+    }
+    Ok(CatResponse {
+        body: text_table(INDICES_COLUMNS, INDICES_NUMERIC_COLUMNS, &rows, verbose),
+        content_type: "text/plain; charset=UTF-8",
+    })
+}
+
+/// Rows with space separated columns padded to the same width, with a header line if `verbose`
+fn text_table(columns: &[&str], numeric: &[&str], rows: &[Vec<String>], verbose: bool) -> String {
+    let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+    let lines: Vec<&Vec<String>> = verbose.then_some(&header).into_iter().chain(rows).collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| lines.iter().map(|line| line[i].len()).max().unwrap_or(0))
+        .collect();
+    let mut table = String::new();
+    for line in lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(columns.iter().zip(&widths))
+            .map(|(cell, (column, &width))| {
+                if numeric.contains(column) {
+                    format!("{:>width$}", cell)
+                } else {
+                    format!("{:<width$}", cell)
+                }
+            })
+            .collect();
+        table.push_str(cells.join(" ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Size in the human readable format of OpenSearch, like `512b` or `12.3kb`
+fn format_size(bytes: usize) -> String {
+    const UNITS: &[&str] = &["kb", "mb", "gb", "tb"];
+    if bytes < 1024 {
+        return format!("{}b", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let size = format!("{:.1}", size);
+    format!("{}{}", size.trim_end_matches(".0"), UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn store() -> DocumentStore {
+        DocumentStore::new(vec![
+            json!({"_index": "songs", "Description": "Through the fire"}),
+            json!({"_index": "songs", "Description": "To the wall"}),
+            json!({"_index": "poems", "Description": "Fire and ice"}),
+        ])
+    }
+
+    #[test]
+    fn indices_are_listed_as_json() {
+        let response = cat_indices(&store(), "format=json").unwrap();
+        assert!(response.content_type.starts_with("application/json"));
+        let rows: Value = serde_json::from_str(&response.body).unwrap();
+        let rows = rows.as_array().unwrap();
+        let counts: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row["index"].as_str().unwrap(),
+                    row["docs.count"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(counts, [("poems", "1"), ("songs", "2")]);
+        assert_eq!(rows[0]["health"], "green");
+    }
+
+    #[test]
+    fn verbose_table_has_a_header() {
+        let response = cat_indices(&store(), "v").unwrap();
+        let lines: Vec<&str> = response.body.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("health status index"), "{}", lines[0]);
+        assert!(lines[2].starts_with("green  open   songs"), "{}", lines[2]);
+        let response = cat_indices(&store(), "").unwrap();
+        assert_eq!(response.body.lines().count(), 2);
+    }
+
+    #[test]
+    fn store_without_indices_is_not_handled() {
+        assert!(cat_indices(&DocumentStore::default(), "").is_err());
+        assert!(cat_indices(&store(), "h=index").is_err());
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(format_size(512), "512b");
+        assert_eq!(format_size(2048), "2kb");
+        assert_eq!(format_size(1536 * 1024), "1.5mb");
+    }
+}
@@ -0,0 +1,81 @@
+//! Emulation of the `_cluster` APIs, for clients checking the cluster before using it.
+
+use crate::store::DocumentStore;
+use serde_json::{json, Value};
+
+/// Name of the emulated cluster
+pub const CLUSTER_NAME: &str = "tinyq";
+
+/// Name of the single node of the emulated cluster
+pub const NODE_NAME: &str = "tinyq-node";
+
+/// Info of the emulated cluster (`GET /`), which clients check before sending requests.
+/// Only the version number is configurable, the build details are made up.
+pub fn root_info(version: &str) -> Value {
+    json!({
+        "name": NODE_NAME,
+        "cluster_name": CLUSTER_NAME,
+        "cluster_uuid": "_na_",
+        "version": {
+            "distribution": "opensearch",
+            "number": version,
+            "build_type": "tar",
+            "build_hash": "unknown",
+            "build_date": "1970-01-01T00:00:00.000000Z",
+            "build_snapshot": false,
+            "lucene_version": "unknown",
+            "minimum_wire_compatibility_version": "7.10.0",
+            "minimum_index_compatibility_version": "7.0.0",
+        },
+        "tagline": "The OpenSearch Project: https://opensearch.org/",
+    })
+}
+
+/// Health of the emulated cluster: always green, a single node with
+/// one primary shard per index and no replicas.
+/// The URL options (`wait_for_status`, `timeout`, ...) are all satisfied already.
+pub fn cluster_health(store: &DocumentStore) -> Value {
+    // The documents without index metadata all belong to the default index
+    let shards = store.indices().len().max(1);
+    json!({
+        "cluster_name": CLUSTER_NAME,
+        "status": "green",
+        "timed_out": false,
+        "number_of_nodes": 1,
+        "number_of_data_nodes": 1,
+        "discovered_master": true,
+        "discovered_cluster_manager": true,
+        "active_primary_shards": shards,
+        "active_shards": shards,
+        "relocating_shards": 0,
+        "initializing_shards": 0,
+        "unassigned_shards": 0,
+        "delayed_unassigned_shards": 0,
+        "number_of_pending_tasks": 0,
+        "number_of_in_flight_fetch": 0,
+        "task_max_waiting_in_queue_millis": 0,
+        "active_shards_percent_as_number": 100.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_info_is_a_version_handshake() {
+        let info = root_info("2.11.1");
+        for field in ["name", "cluster_name", "tagline"] {
+            assert!(info[field].is_string(), "missing {}", field);
+        }
+        assert_eq!(info["version"]["distribution"], "opensearch");
+        // Clients compare the major and minor numbers
+        let number: Vec<u32> = info["version"]["number"]
+            .as_str()
+            .unwrap()
+            .split('.')
+            .map(|part| part.parse().unwrap())
+            .collect();
+        assert_eq!(number, [2, 11, 1]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Emulation of the get document API (`GET /<index>/_doc/<id>`), for get-by-id flows.
+
+use crate::search::{document_id, document_index, document_version, project_source};
+use crate::store::DocumentStore;
+use serde_json::{json, Value};
+
+/// Try to get a document of the store. The body comes with whether it was found,
+/// OpenSearch answers 404 when it wasn't.
+/// Indices without documents in the store aren't emulated, they may be in OpenSearch.
+pub fn get_document(store: &DocumentStore, index: &str, id: &str) -> Result<(bool, Value), String> {
+    let mut documents = store
+        .documents()
+        .iter()
+        .filter(|document| document_index(document) == index)
+        .peekable();
+    if documents.peek().is_none() {
+        return Err(format!(
+            "unimplemented get of a document of index {}, not in the store",
+            index
+        ));
+    }
+    // Documents without an `_id` share the default one, the first of them is the one found
+    match documents.find(|document| document_id(document) == id) {
+        Some(document) => Ok((
+            true,
+            json!({
+                "_index": index,
+                "_id": id,
+                "_version": document_version(document),
+                "_seq_no": 0,
+                "_primary_term": 1,
+                "found": true,
+                "_source": project_source(document, &[], &[]),
+            }),
+        )),
+        None => Ok((
+            false,
+            json!({
+                "_index": index,
+                "_id": id,
+                "found": false,
+            }),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{DEFAULT_INDEX, DOCUMENT_ID};
+
+    #[test]
+    fn gets_the_lyric_by_id() {
+        let (found, document) =
+            get_document(&DocumentStore::default(), DEFAULT_INDEX, DOCUMENT_ID).unwrap();
+        assert!(found);
+        assert_eq!(document["_id"], "1");
+        assert_eq!(document["_index"], DEFAULT_INDEX);
+        let description = document["_source"]["Description"].as_str().unwrap();
+        assert!(description.starts_with("Through the fire"));
+    }
+
+    #[test]
+    fn missing_document_is_not_found() {
+        let (found, document) =
+            get_document(&DocumentStore::default(), DEFAULT_INDEX, "2").unwrap();
+        assert!(!found);
+        assert_eq!(document["found"], false);
+    }
+
+    #[test]
+    fn index_not_in_the_store_is_unsupported() {
+        assert!(get_document(&DocumentStore::default(), "logs", DOCUMENT_ID).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+//! Compressed bodies of the emulated endpoints (`Content-Encoding`/`Accept-Encoding`).
+//! Forwarded requests and responses are passed through as they are.
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+use http::HeaderMap;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// Compression of an HTTP body. `Deflate` is the zlib format, as meant by HTTP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Why a request body couldn't be decompressed
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// Decompressed, it's over the maximum size of the bodies
+    TooLarge,
+    /// Unknown encoding or corrupt data
+    Invalid(String),
+}
+
+/// Request body as sent by the client, decompressed according to its `Content-Encoding`.
+/// A few compressed bytes can decompress to gigabytes, so it's bounded by `max_bytes`
+/// like the compressed body was.
+pub fn decode_request_body<'a>(
+    headers: &HeaderMap,
+    body: &'a [u8],
+    max_bytes: usize,
+) -> Result<Cow<'a, [u8]>, DecodeError> {
+    let Some(content_encoding) = headers.get(CONTENT_ENCODING) else {
+        return Ok(Cow::Borrowed(body));
+    };
+    let content_encoding = content_encoding
+        .to_str()
+        .map_err(|_| DecodeError::Invalid("invalid Content-Encoding header".to_string()))?
+        .trim();
+    if content_encoding.is_empty() || content_encoding.eq_ignore_ascii_case("identity") {
+        return Ok(Cow::Borrowed(body));
+    }
+    let Some(encoding) = Encoding::from_name(content_encoding) else {
+        return Err(DecodeError::Invalid(format!(
+            "unimplemented Content-Encoding: {}",
+            content_encoding
+        )));
+    };
+    let mut decoded = Vec::new();
+    // One byte over the limit tells it's exceeded
+    let limit = max_bytes as u64 + 1;
+    let result = match encoding {
+        Encoding::Gzip => GzDecoder::new(body).take(limit).read_to_end(&mut decoded),
+        Encoding::Deflate => ZlibDecoder::new(body).take(limit).read_to_end(&mut decoded),
+    };
+    result.map_err(|err| {
+        DecodeError::Invalid(format!("invalid {} request body: {}", encoding.name(), err))
+    })?;
+    if decoded.len() > max_bytes {
+        return Err(DecodeError::TooLarge);
+    }
+    Ok(Cow::Owned(decoded))
+}
+
+/// Compression the client accepts for the response, preferring gzip.
+/// Encodings with `q=0` are refused by the client, as per RFC 9110.
+pub fn response_encoding(headers: &HeaderMap) -> Option<Encoding> {
+    let accepted: Vec<Encoding> = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let encoding = Encoding::from_name(params.next()?)?;
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!refused).then_some(encoding)
+        })
+        .collect();
+    [Encoding::Gzip, Encoding::Deflate]
+        .into_iter()
+        .find(|encoding| accepted.contains(encoding))
+}
+
+/// Compress a response body
+pub fn encode(encoding: Encoding, body: &[u8]) -> Vec<u8> {
+    // Writing to a Vec can't fail
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        }
+        Encoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: http::header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn compressed_bodies_round_trip() {
+        let body = br#"{"query": {"multi_match": {"query": "fire"}}}"#;
+        for encoding in [Encoding::Gzip, Encoding::Deflate] {
+            let headers = headers(CONTENT_ENCODING, encoding.name());
+            let encoded = encode(encoding, body);
+            let decoded = decode_request_body(&headers, &encoded, body.len()).unwrap();
+            assert_eq!(&*decoded, body);
+        }
+    }
+
+    #[test]
+    fn plain_body_is_borrowed() {
+        let decoded = decode_request_body(&HeaderMap::new(), b"{}", 2).unwrap();
+        assert!(matches!(decoded, Cow::Borrowed(b"{}")));
+        let headers = headers(CONTENT_ENCODING, "br");
+        assert!(decode_request_body(&headers, b"{}", 2).is_err());
+    }
+
+    #[test]
+    fn gzip_is_preferred_unless_refused() {
+        let accepted = |value| response_encoding(&headers(ACCEPT_ENCODING, value));
+        assert_eq!(accepted("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(accepted("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(accepted("br"), None);
+    }
+
+    #[test]
+    fn decompression_stops_at_the_limit() {
+        let bomb = encode(Encoding::Gzip, &vec![b' '; 10 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024);
+        let headers = headers(CONTENT_ENCODING, "gzip");
+        let decoded = decode_request_body(&headers, &bomb, 1024 * 1024);
+        assert_eq!(decoded, Err(DecodeError::TooLarge));
+        let decoded = decode_request_body(&headers, &bomb, 10 * 1024 * 1024).unwrap();
+        assert_eq!(decoded.len(), 10 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::alias::FieldAliases;
+use crate::analyze::{analyze, normalize, tokenize};
+use crate::geo::GeoFilter;
+use crate::search::{document_id, document_index, document_version, METADATA_FIELDS};
+use regex::Regex;
+use serde_json::json;
+use serde_json::Value;
+use serde_json::Value::Object;
+use std::collections::HashSet;
+
+/// Single parsed query clause, deciding whether a document matches
+#[derive(Debug)]
+pub enum Filter {
+    /// Any of the terms occurs in any of the fields (all fields if empty)
+    MultiMatch {
+        terms: Vec<String>,
+        fields: Vec<String>,
+    },
+    /// Any of the terms occurs in the field
+    Match {
+        field: String,
+        terms: Vec<String>,
+    },
+    /// All the terms occur in the field next to each other, in the same order
+    MatchPhrase {
+        field: String,
+        terms: Vec<String>,
+    },
+    /// Field is equal to the value, see `values_match`
+    Term {
+        field: String,
+        value: Value,
+    },
+    /// Field is exactly equal to one of the values, none match when there are no values
+    Terms {
+        field: String,
+        values: Vec<String>,
+    },
+    /// Field has a value, neither null nor an empty array
+    Exists {
+        field: String,
+    },
+    /// Field is a number within the bounds, the ones which aren't None.
+    /// A numeric string counts as a number when lenient.
+    Range {
+        field: String,
+        gt: Option<f64>,
+        gte: Option<f64>,
+        lt: Option<f64>,
+        lte: Option<f64>,
+    },
+    Geo(GeoFilter),
+    /// A string value of the field, or one of its tokens, matches the whole pattern,
+    /// see `wildcard_regex`. Every value is scanned, so leading wildcards are no slower here,
+    /// but all the documents are when there's no text clause narrowing them down.
+    Wildcard {
+        field: String,
+        pattern: Regex,
+    },
+    /// A string value of the field, or one of its tokens, starts with the prefix.
+    /// The prefix is lowercased when case-insensitive.
+    Prefix {
+        field: String,
+        prefix: String,
+        case_insensitive: bool,
+    },
+    /// Bool query nested in a clause, matching like the top-level one. In a must or should
+    /// clause, its own must and should text clauses add to the score of the parent.
+    Bool(BoolQuery),
+}
+
+/// Clauses of a bool query
+#[derive(Debug, Default)]
+pub struct BoolQuery {
+    /// The must clauses, all of them have to match and add to the score
+    pub must: Vec<Filter>,
+    /// The filter clauses, all of them have to match without adding to the score
+    pub filters: Vec<Filter>,
+    /// Each must_not clause with its filters, none of them may match
+    pub must_not: Vec<Vec<Filter>>,
+    /// Each should clause with its filters, at least `minimum_should_match` have to match
+    pub should: Vec<Vec<Filter>>,
+    pub minimum_should_match: usize,
+}
+
+impl BoolQuery {
+    pub fn matches(&self, source: &Value, case_sensitive: bool, lenient: bool) -> bool {
+        let clause_matches = |clause: &Vec<Filter>| {
+            clause
+                .iter()
+                .all(|filter| filter.matches(source, case_sensitive, lenient))
+        };
+        self.must
+            .iter()
+            .chain(&self.filters)
+            .all(|filter| filter.matches(source, case_sensitive, lenient))
+            && !self.must_not.iter().any(clause_matches)
+            && self
+                .should
+                .iter()
+                .filter(|clause| clause_matches(clause))
+                .count()
+                >= self.minimum_should_match
+    }
+
+    /// Replace the aliased field names of all the clauses with the stored ones
+    pub fn resolve_aliases(&mut self, aliases: &FieldAliases) {
+        self.must
+            .iter_mut()
+            .chain(self.filters.iter_mut())
+            .chain(self.must_not.iter_mut().flatten())
+            .chain(self.should.iter_mut().flatten())
+            .for_each(|filter| filter.resolve_aliases(aliases));
+    }
+
+    /// Filters of the clauses contributing to the score: the must and should ones,
+    /// including those of the bool queries nested in them
+    pub fn scoring_filters(&self) -> Box<dyn Iterator<Item = &Filter> + '_> {
+        let clauses = self.must.iter().chain(self.should.iter().flatten());
+        Box::new(clauses.flat_map(|filter| match filter {
+            Filter::Bool(nested) => nested.scoring_filters(),
+            _ => Box::new(std::iter::once(filter)),
+        }))
+    }
+
+    /// Filters of the clauses whose terms are highlighted: all but the must_not ones,
+    /// including those of the bool queries nested in them
+    pub fn highlighted_filters(&self) -> Box<dyn Iterator<Item = &Filter> + '_> {
+        let clauses = self
+            .must
+            .iter()
+            .chain(&self.filters)
+            .chain(self.should.iter().flatten());
+        Box::new(clauses.flat_map(|filter| match filter {
+            Filter::Bool(nested) => nested.highlighted_filters(),
+            _ => Box::new(std::iter::once(filter)),
+        }))
+    }
+}
+
+impl Filter {
+    /// Whether the document matches the clause. Text clauses ignore case unless `case_sensitive`,
+    /// term clauses are always exact. Numeric strings compare as numbers when `lenient`.
+    pub fn matches(&self, source: &Value, case_sensitive: bool, lenient: bool) -> bool {
+        match self {
+            Filter::MultiMatch { .. } | Filter::Match { .. } => {
+                self.matched_terms(source, case_sensitive) > 0
+            }
+            Filter::MatchPhrase { field, terms } => {
+                contains_phrase(source, field, terms, case_sensitive)
+            }
+            Filter::Term { field, value } => field_value(source, field)
+                .is_some_and(|stored| values_match(&stored, value, lenient)),
+            Filter::Range {
+                field,
+                gt,
+                gte,
+                lt,
+                lte,
+            } => {
+                let Some(number) =
+                    field_value(source, field).and_then(|value| number_value(&value, lenient))
+                else {
+                    return false;
+                };
+                gt.is_none_or(|gt| number > gt)
+                    && gte.is_none_or(|gte| number >= gte)
+                    && lt.is_none_or(|lt| number < lt)
+                    && lte.is_none_or(|lte| number <= lte)
+            }
+            Filter::Terms { field, values } => field_value(source, field)
+                .as_ref()
+                .and_then(Value::as_str)
+                .is_some_and(|value| values.iter().any(|allowed| allowed == value)),
+            Filter::Exists { field } => match field_value(source, field) {
+                None | Some(Value::Null) => false,
+                Some(Value::Array(values)) => !values.is_empty(),
+                Some(_) => true,
+            },
+            Filter::Geo(geo_filter) => geo_filter.matches(source),
+            Filter::Wildcard { field, pattern } => {
+                any_value_or_token(source, field, |value| pattern.is_match(value))
+            }
+            Filter::Prefix {
+                field,
+                prefix,
+                case_insensitive,
+            } => any_value_or_token(source, field, |value| {
+                normalize(value, !case_insensitive).starts_with(prefix.as_str())
+            }),
+            Filter::Bool(bool_query) => bool_query.matches(source, case_sensitive, lenient),
+        }
+    }
+
+    /// Replace the aliased field names with the stored ones
+    pub fn resolve_aliases(&mut self, aliases: &FieldAliases) {
+        match self {
+            Filter::MultiMatch { fields, .. } => {
+                fields.iter_mut().for_each(|field| aliases.resolve(field))
+            }
+            Filter::Match { field, .. }
+            | Filter::MatchPhrase { field, .. }
+            | Filter::Term { field, .. }
+            | Filter::Terms { field, .. }
+            | Filter::Exists { field }
+            | Filter::Range { field, .. }
+            | Filter::Wildcard { field, .. }
+            | Filter::Prefix { field, .. } => aliases.resolve(field),
+            Filter::Geo(geo_filter) => aliases.resolve(geo_filter.field_mut()),
+            Filter::Bool(bool_query) => bool_query.resolve_aliases(aliases),
+        }
+    }
+
+    /// Query terms and searched fields (all if empty) of the text clauses
+    pub fn text_query(&self) -> Option<(&[String], &[String])> {
+        match self {
+            Filter::MultiMatch { terms, fields } => Some((terms, fields)),
+            Filter::Match { field, terms } | Filter::MatchPhrase { field, terms } => {
+                Some((terms, std::slice::from_ref(field)))
+            }
+            Filter::Term { .. }
+            | Filter::Terms { .. }
+            | Filter::Exists { .. }
+            | Filter::Range { .. }
+            | Filter::Geo(_)
+            | Filter::Wildcard { .. }
+            | Filter::Prefix { .. }
+            | Filter::Bool(_) => None,
+        }
+    }
+
+    /// How many of the query terms are among the tokens of the searched fields
+    fn matched_terms(&self, source: &Value, case_sensitive: bool) -> usize {
+        let Some((terms, fields)) = self.text_query() else {
+            return 0;
+        };
+        let tokens: HashSet<_> = field_tokens(source, fields, case_sensitive)
+            .into_iter()
+            .collect();
+        terms
+            .iter()
+            .filter(|term| tokens.contains(normalize(term, case_sensitive).as_ref()))
+            .count()
+    }
+}
+
+/// Tokens of the given string fields (all if `fields` is empty), in document order
+pub fn field_tokens(source: &Value, fields: &[String], case_sensitive: bool) -> Vec<String> {
+    string_values(source, fields)
+        .iter()
+        .flat_map(|value| analyze(value, case_sensitive))
+        .collect()
+}
+
+/// Regex of a wildcard pattern, `*` matching any characters and `?` a single one,
+/// all the other characters matching themselves
+pub fn wildcard_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+    let mut regex = String::from(if case_insensitive { "(?si)^" } else { "(?s)^" });
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|err| format!("unimplemented wildcard value {}: {}", pattern, err))
+}
+
+/// Whether a string value of the field, whole or one of its tokens, passes the check:
+/// without mappings, the field may be a keyword one as well as a text one
+fn any_value_or_token(source: &Value, field: &str, check: impl Fn(&str) -> bool) -> bool {
+    string_values(source, &[field.to_string()])
+        .into_iter()
+        .any(|value| check(value) || tokenize(value).iter().any(|token| check(token)))
+}
+
+/// Whether the terms occur in a row in one of the string values of the field.
+/// Nothing contains an empty phrase, like in OpenSearch.
+fn contains_phrase(source: &Value, field: &str, terms: &[String], case_sensitive: bool) -> bool {
+    if terms.is_empty() {
+        return false;
+    }
+    let phrase: Vec<_> = terms
+        .iter()
+        .map(|term| normalize(term, case_sensitive))
+        .collect();
+    string_values(source, &[field.to_string()])
+        .iter()
+        .any(|value| {
+            analyze(value, case_sensitive)
+                .windows(phrase.len())
+                .any(|window| {
+                    window
+                        .iter()
+                        .zip(&phrase)
+                        .all(|(token, term)| token == term)
+                })
+        })
+}
+
+/// Whether a stored value is equal to the value of a term query. Numbers are equal whatever
+/// their representation (5 and 5.0). Values of different types never match, unless `lenient`
+/// where numeric and boolean strings match the numbers and booleans they spell.
+pub(crate) fn values_match(stored: &Value, query: &Value, lenient: bool) -> bool {
+    match (stored, query) {
+        (Value::Number(_), _) | (_, Value::Number(_)) => {
+            match (number_value(stored, lenient), number_value(query, lenient)) {
+                (Some(stored), Some(query)) => stored == query,
+                _ => false,
+            }
+        }
+        (Value::Bool(stored), Value::String(query))
+        | (Value::String(query), Value::Bool(stored))
+            if lenient =>
+        {
+            query.parse::<bool>().is_ok_and(|query| query == *stored)
+        }
+        _ => stored == query,
+    }
+}
+
+/// Number of a numeric value, or of a numeric string when `lenient`
+fn number_value(value: &Value, lenient: bool) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) if lenient => string
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|number| number.is_finite()),
+        _ => None,
+    }
+}
+
+/// Value of the document field, including metadata fields like `_index`
+fn field_value(source: &Value, field: &str) -> Option<Value> {
+    match field {
+        "_index" => Some(json!(document_index(source))),
+        "_id" => Some(json!(document_id(source))),
+        "_version" => Some(json!(document_version(source))),
+        _ => source.get(field).cloned(),
+    }
+}
+
+/// Top-level string fields of the document source, only the given ones if `fields` isn't empty.
+fn string_values<'a>(source: &'a Value, fields: &[String]) -> Vec<&'a str> {
+    let Object(source) = source else {
+        return Vec::new();
+    };
+    source
+        .iter()
+        // The metadata fields are only searched when asked for
+        .filter(|(field, _)| {
+            fields.contains(field)
+                || (fields.is_empty() && !METADATA_FIELDS.contains(&field.as_str()))
+        })
+        .filter_map(|(_, value)| value.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_match_numbers_only_when_lenient() {
+        assert!(!values_match(&json!(5), &json!("5"), false));
+        assert!(!values_match(&json!("5"), &json!(5), false));
+        assert!(values_match(&json!(5), &json!("5"), true));
+        assert!(values_match(&json!("5.0"), &json!(5), true));
+        assert!(!values_match(&json!(5), &json!("five"), true));
+        assert!(values_match(&json!(true), &json!("true"), true));
+        assert!(!values_match(&json!(true), &json!("true"), false));
+    }
+
+    #[test]
+    fn numbers_match_whatever_their_representation() {
+        assert!(values_match(&json!(5), &json!(5.0), false));
+        assert!(!values_match(&json!(5), &json!(6), false));
+        assert!(values_match(&json!("fire"), &json!("fire"), false));
+        assert!(!values_match(&json!(null), &json!(0), true));
+    }
+}
@@ -0,0 +1,120 @@
+//! Geo filters (`geo_bounding_box`, `geo_distance`) on the location fields of the documents.
+//! Without mappings, a field holding `{"lat": .., "lon": ..}` or `[lon, lat]` is a geo point.
+
+use serde_json::Value;
+use serde_json::Value::{Array, Object};
+
+/// Mean Earth radius in meters, as used by OpenSearch for arc distances
+const EARTH_RADIUS_METERS: f64 = 6371008.7714;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Filter on a geo point field of the document
+#[derive(Debug)]
+pub enum GeoFilter {
+    BoundingBox {
+        field: String,
+        top_left: GeoPoint,
+        bottom_right: GeoPoint,
+    },
+    Distance {
+        field: String,
+        center: GeoPoint,
+        distance_meters: f64,
+    },
+}
+
+impl GeoFilter {
+    pub fn field_mut(&mut self) -> &mut String {
+        match self {
+            GeoFilter::BoundingBox { field, .. } | GeoFilter::Distance { field, .. } => field,
+        }
+    }
+
+    /// Whether the document location falls inside the filter area.
+    /// Documents without a (valid) location never match.
+    pub fn matches(&self, source: &Value) -> bool {
+        match self {
+            GeoFilter::BoundingBox {
+                field,
+                top_left,
+                bottom_right,
+            } => {
+                let Some(point) = source.get(field).and_then(parse_point) else {
+                    return false;
+                };
+                let lat_matches = point.lat <= top_left.lat && point.lat >= bottom_right.lat;
+                // Box crossing the antimeridian has its left edge east of its right edge
+                let lon_matches = if top_left.lon <= bottom_right.lon {
+                    point.lon >= top_left.lon && point.lon <= bottom_right.lon
+                } else {
+                    point.lon >= top_left.lon || point.lon <= bottom_right.lon
+                };
+                lat_matches && lon_matches
+            }
+            GeoFilter::Distance {
+                field,
+                center,
+                distance_meters,
+            } => {
+                let Some(point) = source.get(field).and_then(parse_point) else {
+                    return false;
+                };
+                haversine_distance(center, &point) <= *distance_meters
+            }
+        }
+    }
+}
+
+/// Parse geo point given as `{"lat": .., "lon": ..}` or `[lon, lat]`
+pub fn parse_point(value: &Value) -> Option<GeoPoint> {
+    match value {
+        Object(point) => Some(GeoPoint {
+            lat: point.get("lat")?.as_f64()?,
+            lon: point.get("lon")?.as_f64()?,
+        }),
+        Array(point) if point.len() == 2 => Some(GeoPoint {
+            lat: point[1].as_f64()?,
+            lon: point[0].as_f64()?,
+        }),
+        _ => None,
+    }
+}
+
+/// Parse distance like `10km`, `500m` or `3mi` into meters. Plain numbers are meters.
+pub fn parse_distance(value: &Value) -> Result<f64, String> {
+    if let Some(meters) = value.as_f64() {
+        return Ok(meters);
+    }
+    let Some(distance) = value.as_str() else {
+        return Err(format!("unimplemented distance value: {}", value));
+    };
+    let distance = distance.trim();
+    let unit_start = distance
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(distance.len());
+    let (amount, unit) = distance.split_at(unit_start);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("unimplemented distance value: {}", value))?;
+    let meters_per_unit = match unit.trim() {
+        "" | "m" | "meters" => 1.0,
+        "km" | "kilometers" => 1000.0,
+        "mi" | "miles" => 1609.344,
+        _ => return Err(format!("unimplemented distance unit: {}", value)),
+    };
+    Ok(amount * meters_per_unit)
+}
+
+/// Great-circle distance between two points in meters
+fn haversine_distance(a: &GeoPoint, b: &GeoPoint) -> f64 {
+    let d_lat = (b.lat - a.lat).to_radians();
+    let d_lon = (b.lon - a.lon).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2)
+        + a.lat.to_radians().cos() * b.lat.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().asin()
+}
@@ -0,0 +1,160 @@
+//! Highlighting of the query terms in the hits, like the OpenSearch unified highlighter
+//! with `number_of_fragments: 0`: whole field values are returned, never fragments of them.
+
+use crate::alias::FieldAliases;
+use crate::analyze::{normalize, token_spans};
+use crate::filter::Filter;
+use crate::search::{wildcard_matches, METADATA_FIELDS};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// Parsed `highlight` of the request body
+#[derive(Debug)]
+pub struct Highlight {
+    // Field names, or patterns with `*` wildcards like in OpenSearch Dashboards requests
+    fields: Vec<String>,
+    pre_tag: String,
+    post_tag: String,
+}
+
+// Parse `highlight` like {"fields": {"Description": {}}, "pre_tags": ["<b>"], "post_tags": ["</b>"]}
+pub fn parse_highlight(value: &Value) -> Result<Highlight, String> {
+    let Value::Object(highlight) = value else {
+        return Err(format!("unimplemented highlight value: {}", value));
+    };
+    let mut parsed = Highlight {
+        fields: Vec::new(),
+        pre_tag: "<em>".to_string(),
+        post_tag: "</em>".to_string(),
+    };
+    for (key, value) in highlight {
+        match key.as_str() {
+            "fields" => parsed.fields = parse_highlight_fields(value)?,
+            "pre_tags" => parsed.pre_tag = parse_tag(key, value)?,
+            "post_tags" => parsed.post_tag = parse_tag(key, value)?,
+            // Whole values are returned, so the size of the fragments doesn't matter
+            "fragment_size" => {}
+            _ => return Err(format!("unimplemented highlight parameter: {}", key)),
+        }
+    }
+    if parsed.fields.is_empty() {
+        return Err("unimplemented highlight value - no fields".to_string());
+    }
+    Ok(parsed)
+}
+
+// Fields as an object like {"Description": {}}, or an array of those to keep their order
+fn parse_highlight_fields(value: &Value) -> Result<Vec<String>, String> {
+    let objects = match value {
+        Value::Array(objects) => objects.iter().collect(),
+        _ => vec![value],
+    };
+    let mut fields = Vec::new();
+    for object in objects {
+        let Value::Object(object) = object else {
+            return Err(format!("unimplemented highlight fields value: {}", value));
+        };
+        for (field, options) in object {
+            if *options != Value::Object(Map::new()) {
+                return Err(format!(
+                    "unimplemented highlight field options: {}",
+                    options
+                ));
+            }
+            fields.push(field.clone());
+        }
+    }
+    Ok(fields)
+}
+
+// Only a single tag is supported, OpenSearch alternates between them for the terms
+fn parse_tag(key: &str, value: &Value) -> Result<String, String> {
+    match value.as_array().map(Vec::as_slice) {
+        Some([Value::String(tag)]) => Ok(tag.clone()),
+        _ => Err(format!("unimplemented highlight {} value: {}", key, value)),
+    }
+}
+
+impl Highlight {
+    /// Replace the aliased field names with the stored ones, patterns are kept as they are
+    pub fn resolve_aliases(&mut self, aliases: &FieldAliases) {
+        self.fields
+            .iter_mut()
+            .for_each(|field| aliases.resolve(field));
+    }
+
+    /// `highlight` of a hit: the string values of the requested fields with the terms
+    /// of the text clauses searching them wrapped in tags.
+    /// None when none of the values contains any of the terms.
+    pub fn highlight<'a>(
+        &self,
+        source: &Value,
+        filters: impl IntoIterator<Item = &'a Filter>,
+        case_sensitive: bool,
+    ) -> Option<Value> {
+        let Value::Object(source) = source else {
+            return None;
+        };
+        let text_queries: Vec<_> = filters.into_iter().filter_map(Filter::text_query).collect();
+        let mut highlight = Map::new();
+        for (field, value) in source {
+            if METADATA_FIELDS.contains(&field.as_str())
+                || !self
+                    .fields
+                    .iter()
+                    .any(|pattern| wildcard_matches(pattern, field))
+            {
+                continue;
+            }
+            // Like `require_field_match`, only the terms searched in this field are highlighted
+            let terms: HashSet<_> = text_queries
+                .iter()
+                .filter(|(_, fields)| fields.is_empty() || fields.contains(field))
+                .flat_map(|(terms, _)| terms.iter())
+                .map(|term| normalize(term, case_sensitive).into_owned())
+                .collect();
+            if terms.is_empty() {
+                continue;
+            }
+            let values = match value {
+                Value::Array(values) => values.iter().collect(),
+                _ => vec![value],
+            };
+            let fragments: Vec<_> = values
+                .into_iter()
+                .filter_map(Value::as_str)
+                .filter_map(|text| self.highlight_text(text, &terms, case_sensitive))
+                .collect();
+            if !fragments.is_empty() {
+                highlight.insert(field.clone(), json!(fragments));
+            }
+        }
+        (!highlight.is_empty()).then_some(Value::Object(highlight))
+    }
+
+    /// Text with the tokens among the terms wrapped in tags, None if there are none
+    fn highlight_text(
+        &self,
+        text: &str,
+        terms: &HashSet<String>,
+        case_sensitive: bool,
+    ) -> Option<String> {
+        let mut highlighted = String::new();
+        let mut end_of_previous = 0;
+        for (start, end) in token_spans(text) {
+            if !terms.contains(normalize(&text[start..end], case_sensitive).as_ref()) {
+                continue;
+            }
+            highlighted.push_str(&text[end_of_previous..start]);
+            highlighted.push_str(&self.pre_tag);
+            highlighted.push_str(&text[start..end]);
+            highlighted.push_str(&self.post_tag);
+            end_of_previous = end;
+        }
+        if highlighted.is_empty() {
+            return None;
+        }
+        highlighted.push_str(&text[end_of_previous..]);
+        Some(highlighted)
+    }
+}
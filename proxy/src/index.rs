@@ -0,0 +1,110 @@
+//! Inverted index of the documents, narrowing down the documents scanned by text queries.
+//!
+//! Lookups give candidates only: every candidate is still checked against the whole query,
+//! so the index can be a superset of the matches. Tokens are indexed lowercased, which
+//! serves case-sensitive matching too.
+
+use crate::analyze::analyze;
+use crate::search::METADATA_FIELDS;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Token → field → ids (positions in the store) of the documents with the token
+/// in that top-level string field, in ascending order
+pub struct InvertedIndex {
+    postings: HashMap<String, HashMap<String, Vec<usize>>>,
+}
+
+impl InvertedIndex {
+    pub fn build(documents: &[Value]) -> Self {
+        let mut postings: HashMap<String, HashMap<String, Vec<usize>>> = HashMap::new();
+        for (id, document) in documents.iter().enumerate() {
+            let Value::Object(document) = document else {
+                continue;
+            };
+            for (field, value) in document {
+                // Same as searched by the text clauses: only the string values
+                let Value::String(value) = value else {
+                    continue;
+                };
+                for token in analyze(value, false) {
+                    let ids = postings
+                        .entry(token)
+                        .or_default()
+                        .entry(field.clone())
+                        .or_default();
+                    if ids.last() != Some(&id) {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        InvertedIndex { postings }
+    }
+
+    /// Ids of the documents with any of the terms in any of the fields, in ascending order.
+    /// All the fields but the metadata ones are searched when `fields` is empty, like the text clauses do.
+    pub fn lookup(&self, terms: &[String], fields: &[String]) -> Vec<usize> {
+        let mut ids: Vec<usize> = terms
+            .iter()
+            .filter_map(|term| self.postings.get(&term.to_lowercase()))
+            .flat_map(|by_field| {
+                by_field
+                    .iter()
+                    .filter(|(field, _)| {
+                        fields.contains(field)
+                            || (fields.is_empty() && !METADATA_FIELDS.contains(&field.as_str()))
+                    })
+                    .flat_map(|(_, ids)| ids.iter().copied())
+            })
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+/// Ids in both of the ascending lists
+pub fn intersect(a: Vec<usize>, b: Vec<usize>) -> Vec<usize> {
+    let mut b = b.into_iter().peekable();
+    a.into_iter()
+        .filter(|id| {
+            while b.next_if(|other| other < id).is_some() {}
+            b.peek() == Some(id)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn lookup_finds_the_documents_with_any_term() {
+        let index = InvertedIndex::build(&[
+            json!({"Title": "Fire", "Description": "Through the fire"}),
+            json!({"Title": "Wall", "Description": "To the wall", "_id": "fire"}),
+            json!({"Description": "To the limit", "Year": 1984}),
+        ]);
+        let terms = |terms: &[&str]| {
+            terms
+                .iter()
+                .map(|term| term.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(index.lookup(&terms(&["FIRE"]), &[]), [0]);
+        assert_eq!(index.lookup(&terms(&["fire", "limit"]), &[]), [0, 2]);
+        assert_eq!(
+            index.lookup(&terms(&["the"]), &terms(&["Title"])),
+            [] as [usize; 0]
+        );
+        assert_eq!(index.lookup(&terms(&["1984"]), &[]), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn intersect_keeps_the_common_ids() {
+        assert_eq!(intersect(vec![0, 2, 5, 7], vec![1, 2, 3, 7, 9]), [2, 7]);
+        assert_eq!(intersect(vec![0, 1], Vec::new()), [] as [usize; 0]);
+    }
+}
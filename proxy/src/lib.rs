@@ -0,0 +1,16 @@
+//! Emulation of OpenSearch endpoints, independent of the HTTP layer
+//! so it can be called (and benchmarked) directly.
+
+mod aggregation;
+pub mod alias;
+mod analyze;
+pub mod cat;
+pub mod cluster;
+pub mod document;
+mod filter;
+mod geo;
+mod highlight;
+mod index;
+mod scoring;
+pub mod search;
+pub mod store;
@@ -1,502 +1,3646 @@
 // Based on https://github.com/hyperium/hyper/blob/master/examples/gateway.rs
 
-use http::Response;
+mod access_log;
+mod breaker;
+mod bulk;
+mod cache;
+mod encoding;
+mod tls;
+mod upstream;
+
+use access_log::{path_index, rfc3339, AccessLog, AccessLogEntry};
+use breaker::{BreakerSettings, CircuitBreaker};
+use bulk::count_bulk_actions;
+use cache::SearchCache;
+use clap::{ArgAction, Parser, ValueEnum};
+use encoding::{decode_request_body, encode, response_encoding, DecodeError};
+use hdrhistogram::Histogram;
+use http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+    AUTHORIZATION, CONTENT_LENGTH, COOKIE, EXPECT, PROXY_AUTHORIZATION, TRANSFER_ENCODING,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
+use http_body_util::combinators::BoxBody;
 use http_body_util::BodyExt;
-use http_body_util::Full;
+use http_body_util::{Full, Limited};
 use hyper::body::Bytes;
 use hyper::body::Incoming;
-use hyper::Request;
-use hyper::{server::conn::http1, service::service_fn};
-use hyper_util::rt::TokioIo;
+use hyper::service::service_fn;
+use hyper::{Method, Request};
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
 use once_cell::sync::Lazy;
+use percent_encoding::percent_decode_str;
+use proxy::alias::FieldAliases;
+use proxy::cat::cat_indices;
+use proxy::cluster::{cluster_health, root_info};
+use proxy::document::get_document;
+use proxy::search::{
+    count, msearch, normalized_count, normalized_msearch, normalized_search, search, SearchError,
+    SearchInput, SearchSettings, DEFAULT_INDEX, DEFAULT_SIZE, SUPPORTED_FEATURES,
+};
+use proxy::store::DocumentStore;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use regex::Regex;
+use rustls::pki_types::ServerName;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 use serde_json::json;
 use serde_json::Value;
-use serde_json::Value::{Array, Object};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use upstream::Upstreams;
 
-#[derive(Debug)]
-struct Stats {
-    search_queries_success_count: u64,
-    search_queries_failure_count: u64,
-    nonsearch_passed_through_count: u64,
-    search_queries_failures: Vec<(String, Bytes)>,
-}
+/// Serialized for `GET /config` on the monitoring website, with the credentials redacted
+#[derive(Parser, Debug, Serialize)]
+#[command(about = "Proxy emulating a subset of OpenSearch, forwarding the rest")]
+struct Config {
+    /// Address the proxy listens on
+    #[arg(long, env = "TINYQ_LISTEN", default_value = "0.0.0.0:3000")]
+    listen: SocketAddr,
 
-/// Convert a Request with incoming data to a Request with the data streamed in and ready to go
-async fn request_with_streamed_body(
-    req: Request<Incoming>,
-) -> Result<Request<Bytes>, hyper::Error> {
-    let (parts, body) = req.into_parts();
-    let body = body.collect().await?.to_bytes();
-    Ok(Request::from_parts(parts, body))
-}
+    /// Addresses of the OpenSearch nodes, comma-separated, where the requests we don't emulate
+    /// are forwarded. Each request goes to the next node in turn.
+    #[arg(
+        long,
+        env = "TINYQ_UPSTREAM",
+        value_delimiter = ',',
+        default_value = "127.0.0.1:9200"
+    )]
+    upstream: Vec<SocketAddr>,
 
-/// Convert a Request<Bytes> to Request<Full<Bytes>>
-fn request_with_full_body(req: &Request<Bytes>) -> Result<Request<Full<Bytes>>, hyper::Error> {
-    let (parts, body) = req.clone().into_parts();
-    let body = Full::new(body);
-    Ok(Request::from_parts(parts, body))
-}
+    /// Time (in milliseconds) an OpenSearch node failing to accept connections is skipped,
+    /// its requests going to the other nodes
+    #[arg(long, default_value_t = 5000)]
+    upstream_skip_ms: u64,
+
+    /// Connect to OpenSearch over HTTPS
+    #[arg(long)]
+    upstream_tls: bool,
+
+    /// PEM file with the CA certificates trusted for OpenSearch, instead of the system ones
+    #[arg(long, requires = "upstream_tls")]
+    upstream_ca_cert: Option<PathBuf>,
+
+    /// Accept any OpenSearch certificate, only for self-signed development clusters
+    #[arg(long, requires = "upstream_tls", conflicts_with = "upstream_ca_cert")]
+    upstream_tls_insecure: bool,
+
+    /// Name the OpenSearch certificate is checked against, when it isn't issued for the upstream IP
+    #[arg(long, requires = "upstream_tls")]
+    upstream_tls_server_name: Option<String>,
+
+    /// Authorization header (`Basic <base64 user:password>` or `ApiKey <base64 id:key>`)
+    /// added to forwarded requests without one. Emulated searches then require this one.
+    #[arg(long, env = "TINYQ_UPSTREAM_AUTH", value_parser = parse_upstream_auth, hide_env_values = true)]
+    #[serde(serialize_with = "serialize_redacted")]
+    upstream_auth: Option<HeaderValue>,
+
+    /// Origin allowed to call the proxy from a browser (`*` for any), sent in the
+    /// `Access-Control-Allow-Origin` header of the responses. CORS preflight requests are answered then.
+    #[arg(long, env = "TINYQ_CORS_ORIGIN", value_parser = HeaderValue::from_str)]
+    #[serde(serialize_with = "serialize_header_value")]
+    cors_origin: Option<HeaderValue>,
+
+    /// Header added to the responses of the proxy itself (emulated or rejected requests,
+    /// not forwarded ones), e.g. `X-Served-By: tinyq`. Can be given multiple times.
+    /// Headers the response already has, like `Content-Type`, are kept.
+    #[arg(long, value_parser = parse_response_header)]
+    response_header: Vec<ResponseHeader>,
+
+    /// Replace the headers the responses already have with the `--response-header` ones
+    #[arg(long, requires = "response_header")]
+    override_response_headers: bool,
+
+    /// Address the monitoring website listens on
+    #[arg(long, env = "TINYQ_MONITOR_LISTEN", default_value = "0.0.0.0:3001")]
+    monitor_listen: SocketAddr,
+
+    /// Request header identifying the tenant, used to break down the stats per tenant
+    #[arg(long, default_value = "x-tenant-id")]
+    tenant_header: String,
+
+    /// Maximum number of tenants tracked separately, the rest is counted as "other"
+    #[arg(long, default_value_t = 100)]
+    max_tenants: usize,
+
+    /// Rewrite path prefix of forwarded requests, e.g. `/products/=/catalog-v2/`.
+    /// Can be given multiple times, rules are applied in order.
+    #[arg(long, value_parser = parse_path_rewrite)]
+    path_rewrite: Vec<PathRewrite>,
+
+    /// Fraction (0.0 - 1.0) of forwarded requests recorded in the passthrough samples log
+    #[arg(long, default_value_t = 0.0)]
+    sample_passthrough: f64,
+
+    /// Check at startup that OpenSearch accepts connections
+    #[arg(long)]
+    startup_probe_backend: bool,
+
+    /// Abort startup if the startup probe can't connect to OpenSearch
+    #[arg(long, requires = "startup_probe_backend")]
+    require_backend: bool,
+
+    /// Interval (in milliseconds) of the background connection checks of OpenSearch,
+    /// reported by the health check of the proxy port (`GET /_tinyq/health`)
+    #[arg(long, default_value_t = 5000, value_parser = clap::value_parser!(u64).range(1..))]
+    health_probe_interval_ms: u64,
 
-/// Convert a Response with incoming data to a Response with the data streamed in and ready to go
-async fn response_with_streamed_body(
-    res: Response<Incoming>,
-) -> Result<Response<Full<Bytes>>, hyper::Error> {
-    let (parts, body) = res.into_parts();
-    let body = body.collect().await?.to_bytes();
-    Ok(Response::from_parts(parts, Full::new(body)))
+    /// Fraction (0.0 - 1.0) of emulated responses reporting a (fake) failed shard,
+    /// to exercise client handling of partial failures
+    #[arg(long, default_value_t = 0.0)]
+    inject_shard_failures: f64,
+
+    /// Delay (in milliseconds) added to each emulated search response, to test slow clusters
+    #[arg(long, default_value_t = 0)]
+    chaos_latency_ms: u64,
+
+    /// Fraction (0.0 - 1.0) of emulated searches answered with a (fake) 503 instead
+    #[arg(long, default_value_t = 0.0)]
+    chaos_error_rate: f64,
+
+    /// Seed of the random chaos errors, for reproducible runs
+    #[arg(long)]
+    chaos_seed: Option<u64>,
+
+    /// Field names queried by the clients which are stored under another name, as a JSON
+    /// object like `{"message": "Description"}`
+    #[arg(long, env = "TINYQ_FIELD_ALIASES", value_parser = FieldAliases::parse, default_value = "{}")]
+    field_aliases: FieldAliases,
+
+    /// Number of hits of the emulated searches without an explicit `size`,
+    /// 10 like in OpenSearch: an empty `match_all` doesn't return the whole store
+    #[arg(long, default_value_t = DEFAULT_SIZE)]
+    default_size: usize,
+
+    /// `took` (in milliseconds) reported by the emulated searches instead of the time
+    /// they actually took, for deterministic tests
+    #[arg(long)]
+    fixed_took: Option<u64>,
+
+    /// Maximum time (in milliseconds) spent on an emulated search, partial results are returned after it
+    #[arg(long, default_value_t = 30000)]
+    max_search_timeout_ms: u64,
+
+    /// Maximum size of the request bodies, larger requests are answered with a 413
+    /// before being emulated or forwarded
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_body_bytes: usize,
+
+    /// Maximum number of search failures (reason and request body) kept for the monitoring website
+    #[arg(long, default_value_t = 1000)]
+    max_failures: usize,
+
+    /// Maximum number of emulated search responses cached for identical searches, 0 disables the cache
+    #[arg(long, default_value_t = 1000)]
+    search_cache_size: usize,
+
+    /// Maximum time (in milliseconds) waiting for OpenSearch to respond to a forwarded request
+    #[arg(long, default_value_t = 30000)]
+    upstream_timeout_ms: u64,
+
+    /// Number of times a forwarded read or search is retried when OpenSearch can't be reached,
+    /// other requests are never sent twice
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(0..=10))]
+    upstream_retries: u32,
+
+    /// Wait (in milliseconds) before the first retry, doubled before each of the next ones
+    #[arg(long, default_value_t = 50)]
+    upstream_retry_backoff_ms: u64,
+
+    /// Failures to reach OpenSearch within `--breaker-window-ms` opening the circuit breaker:
+    /// forwarded requests are then answered with a 503 right away. 0 disables the breaker.
+    #[arg(long, default_value_t = 5)]
+    breaker_threshold: u32,
+
+    /// Window (in milliseconds) in which the failures opening the circuit breaker are counted
+    #[arg(long, default_value_t = 10000)]
+    breaker_window_ms: u64,
+
+    /// Time (in milliseconds) the circuit breaker stays open, before a trial request is let through
+    #[arg(long, default_value_t = 5000)]
+    breaker_cooldown_ms: u64,
+
+    /// Maximum number of idle connections to OpenSearch kept for reuse
+    #[arg(long, default_value_t = 32)]
+    upstream_pool_size: usize,
+
+    /// Match text queries (multi_match, match, ...) exactly instead of ignoring case
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Answer _cluster/health with a green cluster instead of forwarding it,
+    /// so health checks pass while OpenSearch is down
+    #[arg(long)]
+    emulate_cluster_health: bool,
+
+    /// Answer `GET /` with the cluster info instead of forwarding it,
+    /// so clients can initialize while OpenSearch is down
+    #[arg(long)]
+    emulate_root_info: bool,
+
+    /// OpenSearch version reported by the emulated `GET /`
+    #[arg(long, default_value = "2.11.0")]
+    root_info_version: String,
+
+    /// Which requests that aren't emulated are forwarded to OpenSearch
+    #[arg(long, value_enum, default_value_t = ForwardPolicy::All)]
+    forward_policy: ForwardPolicy,
+
+    /// Regex of the paths forwarded with `--forward-policy allowlist`, e.g. `^/_cat/`.
+    /// Can be given multiple times, a path matching any of them is forwarded.
+    #[arg(long, required_if_eq("forward_policy", "allowlist"))]
+    #[serde(serialize_with = "serialize_regexes")]
+    forward_allow: Vec<Regex>,
+
+    /// Run without OpenSearch: nothing is forwarded, like with `--forward-policy none`,
+    /// and unsupported queries are answered with a 501 telling what isn't supported
+    #[arg(long, conflicts_with_all = ["forward_policy", "forward_allow"])]
+    standalone: bool,
+
+    /// Maximum time (in milliseconds) waiting for in-flight requests to finish on SIGINT/SIGTERM
+    #[arg(long, default_value_t = 10000)]
+    shutdown_grace_ms: u64,
+
+    /// Allow zeroing the stats with POST /reset on the monitoring website
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    allow_reset: bool,
+
+    /// Allow reloading the documents file with POST /_tinyq/reload on the proxy port
+    #[arg(long, requires = "documents")]
+    allow_reload: bool,
+
+    /// JSON file with an array of documents served by the emulated endpoints,
+    /// the built-in lyrics are used when not given
+    #[arg(long)]
+    documents: Option<PathBuf>,
+
+    /// Directory with the monitoring website (index.html, favicon.ico) served instead of
+    /// the one built into the binary, e.g. to work on the frontend without rebuilding
+    #[arg(long)]
+    frontend_dir: Option<PathBuf>,
+
+    /// File the access log is appended to, one JSON object per request, `-` for stdout
+    #[arg(long, env = "TINYQ_ACCESS_LOG")]
+    access_log: Option<PathBuf>,
 }
 
-/// We don't know how to handle this request, so let's forward it to OpenSearch instead
-async fn forward_request_to_opensearch(
-    out_addr: &SocketAddr,
-    req: &Request<Bytes>,
-) -> Result<Response<http_body_util::Full<hyper::body::Bytes>>, hyper::Error> {
-    let client_stream = TcpStream::connect(out_addr).await.unwrap();
-    let io = TokioIo::new(client_stream);
+impl Config {
+    /// Forward policy in effect, nothing is forwarded in standalone mode
+    fn forward_policy(&self) -> ForwardPolicy {
+        if self.standalone {
+            ForwardPolicy::None
+        } else {
+            self.forward_policy
+        }
+    }
+
+    fn upstreams(&self) -> Upstreams {
+        Upstreams::new(
+            &self.upstream,
+            self.upstream_tls,
+            Duration::from_millis(self.upstream_skip_ms),
+        )
+    }
+
+    fn upstream_timeout(&self) -> Duration {
+        Duration::from_millis(self.upstream_timeout_ms)
+    }
 
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-    tokio::task::spawn(async move {
-        if let Err(err) = conn.await {
-            println!("Connection failed: {:?}", err);
+    fn upstream_retry(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.upstream_retries,
+            backoff: Duration::from_millis(self.upstream_retry_backoff_ms),
         }
-    });
+    }
 
-    let res = sender.send_request(request_with_full_body(req)?).await?;
-    let res = response_with_streamed_body(res).await?;
-    Ok(res)
+    fn upstream_breaker(&self) -> BreakerSettings {
+        BreakerSettings {
+            threshold: self.breaker_threshold,
+            window: Duration::from_millis(self.breaker_window_ms),
+            cooldown: Duration::from_millis(self.breaker_cooldown_ms),
+        }
+    }
+
+    fn shutdown_grace(&self) -> Duration {
+        Duration::from_millis(self.shutdown_grace_ms)
+    }
+
+    fn search_settings(&self) -> SearchSettings {
+        SearchSettings {
+            max_timeout: Duration::from_millis(self.max_search_timeout_ms),
+            case_sensitive: self.case_sensitive,
+            field_aliases: self.field_aliases.clone(),
+            default_size: self.default_size,
+            fixed_took: self.fixed_took,
+        }
+    }
+}
+
+/// Only whether the credentials are set, never their value
+fn serialize_redacted<S: Serializer>(
+    value: &Option<HeaderValue>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+fn serialize_header_value<S: Serializer>(
+    value: &Option<HeaderValue>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .serialize(serializer)
 }
 
-// Parsing _search request:
+fn serialize_regexes<S: Serializer>(regexes: &[Regex], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(regexes.iter().map(Regex::as_str))
+}
 
-struct ParsedSearchRequest {
-    multi_match: String,
+/// Which requests that aren't emulated are forwarded to OpenSearch
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ForwardPolicy {
+    /// Forward everything
+    All,
+    /// Forward nothing, answering 404
+    None,
+    /// Forward the paths matching `--forward-allow`, answering 403 to the others
+    Allowlist,
 }
 
-fn parse_options(
-    options: &Vec<Vec<&str>>,
-    _parsed: &mut ParsedSearchRequest,
-) -> Result<(), String> {
-    for option in options {
-        match option.first() {
-            // We will ignore those options for now, they don't really affect the end results
-            Some(&"ignore_unavailable") => {}
-            Some(&"track_total_hits") => {}
-            Some(&"timeout") => {}
-            Some(&"preference") => {}
+/// Request not emulated which the forward policy doesn't allow to forward
+enum Rejection {
+    NotFound,
+    Forbidden,
+    /// Emulated endpoint, with a request which isn't supported (in standalone mode)
+    NotImplemented(String),
+}
 
-            Some(option) => return Err(format!("unsupported URL option {}", option)),
-            None => {}
+impl ForwardPolicy {
+    /// Why the request with this path can't be forwarded, None if it can
+    fn rejection(self, allowlist: &[Regex], path: &str) -> Option<Rejection> {
+        match self {
+            ForwardPolicy::All => None,
+            ForwardPolicy::None => Some(Rejection::NotFound),
+            ForwardPolicy::Allowlist if allowlist.iter().any(|allowed| allowed.is_match(path)) => {
+                None
+            }
+            ForwardPolicy::Allowlist => Some(Rejection::Forbidden),
         }
     }
-    Ok(())
 }
 
-// Parse query filters like match_all or multi_match
-fn parse_filter(
-    value: &Value,
-    filter: &serde_json::Map<String, Value>,
-    parsed: &mut ParsedSearchRequest,
-) -> Result<(), String> {
-    let filter_keys: Vec<_> = filter.keys().collect();
-    if filter_keys == vec!["match_all"] {
-        let Some(Object(match_all_filter)) = filter.get("match_all") else {
-            return Err(format!(
-                "unimplemented query value - unexpected match_all filter: {}",
-                value
-            ));
-        };
-        if !match_all_filter.keys().collect::<Vec<_>>().is_empty() {
-            return Err(format!(
-                "unimplemented query value - non-empty match_all filter: {}",
-                value
-            ));
-        }
-    } else if filter_keys == vec!["multi_match"] {
-        let Some(Object(multi_match_filter)) = filter.get("multi_match") else {
-            return Err(format!(
-                "unimplemented query value - unexpected multi_match filter: {}",
-                value
-            ));
-        };
-        for (filter_key, filter_value) in multi_match_filter {
-            match filter_key.as_str() {
-                "lenient" => {}
-                "type" => {
-                    if *filter_value != "best_fields" {
-                        return Err(format!(
-                            "unimplemented multi_match type value: {}",
-                            filter_value
-                        ));
-                    }
-                }
-                "query" => {
-                    if let Value::String(filter_value) = filter_value {
-                        parsed.multi_match = filter_value.clone();
-                    } else {
-                        return Err(format!(
-                            "unimplemented multi_match query value: {}",
-                            filter_value
-                        ));
-                    }
-                }
-                _ => {
-                    return Err(format!(
-                        "unimplemented multi_match parameter: {}",
-                        filter_key
-                    ));
-                }
+impl Rejection {
+    /// Response rejecting the request, in the OpenSearch error format
+    fn response(&self, req: &Request<Bytes>) -> Response<ProxyBody> {
+        let (status, error_type, reason) = match self {
+            Rejection::NotFound => (
+                404,
+                "forwarding_disabled",
+                "isn't emulated and forwarding to OpenSearch is disabled",
+            ),
+            Rejection::Forbidden => (
+                403,
+                "forwarding_forbidden",
+                "isn't emulated and isn't allowed to be forwarded to OpenSearch",
+            ),
+            Rejection::NotImplemented(reason) => {
+                (501, "not_implemented_exception", reason.as_str())
             }
-        }
-    } else {
-        return Err(format!(
-            "unimplemented query value - unexpected filter: {}",
-            value
-        ));
+        };
+        let reason = format!("[{} {}] {}", req.method(), req.uri().path(), reason);
+        let body = json!({
+            "error": {
+                "root_cause": [{"type": error_type, "reason": reason}],
+                "type": error_type,
+                "reason": reason,
+            },
+            "status": status,
+        });
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json; charset=UTF-8")
+            .body(full_body(Bytes::from(body.to_string())))
+            .unwrap()
     }
+}
 
-    Ok(())
+#[derive(Clone, Debug, Serialize)]
+struct PathRewrite {
+    from: String,
+    to: String,
 }
 
-fn parse_body(body: &Value, parsed: &mut ParsedSearchRequest) -> Result<(), String> {
-    let Object(map) = body else {
+fn parse_path_rewrite(rule: &str) -> Result<PathRewrite, String> {
+    let Some((from, to)) = rule.split_once('=') else {
+        return Err(format!("expected FROM=TO path rewrite rule, got {}", rule));
+    };
+    if !from.starts_with('/') || !to.starts_with('/') {
         return Err(format!(
-            "expected JSON object in search body but got {}",
-            body
+            "path rewrite rule must use absolute paths, got {}",
+            rule
         ));
-    };
-    for (key, value) in map {
-        match key.as_str() {
-            "_source" => {
-                let mut expected_source = serde_json::Map::new();
-                expected_source.insert("excludes".to_string(), Array(Vec::new()));
-                if *value != Object(expected_source) {
-                    return Err(format!("unimplemented _source value: {}", value));
-                }
-            }
-            "docvalue_fields" => {
-                if *value != Array(Vec::new()) {
-                    return Err(format!("unimplemented docvalue_fields value: {}", value));
-                }
-            }
-            "highlight" => {
-                // Just ignore it completely for now, this doesn't seem to affect the end results contents
-            }
-            "query" => {
-                // Expect query to be:
-                //     "bool": Object {
-                //        "filter": Array [
-                //            Object {
-                //                "match_all": Object {},
-                //            },
-                //        ],
-                //        "must": Array [],
-                //        "must_not": Array [],
-                //        "should": Array [],
-                //    }
-
-                let Object(query) = value else {
-                    return Err(format!(
-                        "unimplemented query value - expected JSON object: {}",
-                        value
-                    ));
-                };
-                let query_keys: Vec<_> = query.keys().collect();
-                if query_keys != vec!["bool"] {
-                    return Err(format!(
-                        "unimplemented query value - many query keys: {}",
-                        value
-                    ));
-                }
-
-                let query = query.get("bool").unwrap();
-                let Object(query) = query else {
-                    return Err(format!("unimplemented query value: {}", value));
-                };
-
-                let Some(Array(filter)) = query.get("filter") else {
-                    return Err(format!(
-                        "unimplemented query value - expected 'filter': {}",
-                        value
-                    ));
-                };
-                if filter.len() != 1 {
-                    return Err(format!(
-                        "unimplemented query value - expected one element 'filter': {}",
-                        value
-                    ));
-                }
-                let Some(Object(filter)) = filter.first() else {
-                    return Err(format!("unimplemented query value - expected first element of 'filter' to be JSON object: {}", value));
-                };
-                parse_filter(value, filter, parsed)?;
-
-                let Some(Array(must)) = query.get("must") else {
-                    return Err(format!(
-                        "unimplemented query value - expected 'must': {}",
-                        value
-                    ));
-                };
-                if !must.is_empty() {
-                    return Err(format!(
-                        "unimplemented query value - expected empty 'must': {}",
-                        value
-                    ));
-                }
+    }
+    Ok(PathRewrite {
+        from: from.to_string(),
+        to: to.to_string(),
+    })
+}
 
-                let Some(Array(must_not)) = query.get("must_not") else {
-                    return Err(format!(
-                        "unimplemented query value - expected 'must_not': {}",
-                        value
-                    ));
-                };
-                if !must_not.is_empty() {
-                    return Err(format!(
-                        "unimplemented query value - expected empty 'must_not': {}",
-                        value
-                    ));
-                }
+/// Header added by `--response-header`
+#[derive(Clone, Debug)]
+struct ResponseHeader {
+    name: HeaderName,
+    value: HeaderValue,
+}
 
-                let Some(Array(should)) = query.get("should") else {
-                    return Err(format!(
-                        "unimplemented query value - expected 'should': {}",
-                        value
-                    ));
-                };
-                if !should.is_empty() {
-                    return Err(format!(
-                        "unimplemented query value - expected empty 'should': {}",
-                        value
-                    ));
-                }
-            }
-            "script_fields" => {
-                if *value != Object(serde_json::Map::new()) {
-                    return Err(format!("unimplemented script_fields value: {}", value));
-                }
-            }
-            "size" => {
-                // Let's ignore it for now, always returning everything...
-            }
-            "sort" => {
-                // Let's ignore it for now, returning in any order
-            }
-            "stored_fields" => {
-                if *value != Array(vec![Value::String("*".to_string())]) {
-                    return Err(format!("unimplemented stored_fields value: {}", value));
-                }
-            }
-            "version" => {
-                // Let's ignore it for now, returning always with version
-            }
-            _ => {
-                return Err(format!("unimplemented search parameter: {}", key));
-            }
-        }
+/// As given on the command line, `name: value`
+impl Serialize for ResponseHeader {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = String::from_utf8_lossy(self.value.as_bytes());
+        serializer.collect_str(&format_args!("{}: {}", self.name, value))
     }
-    Ok(())
 }
 
-/// Try to handle request to _search endpoint. If we can handle it,
-/// return a hardcoded list of results, else return an error.
-async fn handle_search_request(
-    req: &Request<Bytes>,
-) -> Result<Response<http_body_util::Full<hyper::body::Bytes>>, String> {
-    let mut parsed_request: ParsedSearchRequest = ParsedSearchRequest {
-        multi_match: "".to_string(),
+fn parse_response_header(header: &str) -> Result<ResponseHeader, String> {
+    let Some((name, value)) = header.split_once(':') else {
+        return Err(format!("expected `name: value` header, got {}", header));
     };
+    Ok(ResponseHeader {
+        name: HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name {}", name.trim()))?,
+        value: HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("invalid value of header {}", name.trim()))?,
+    })
+}
 
-    let options: Vec<Vec<_>> = req
-        .uri()
-        .query()
-        .unwrap_or("")
-        .split('&')
-        .map(|elem| elem.split('=').collect())
+/// Add the `--response-header` headers to a response of the proxy.
+/// The ones it already has are kept, unless replacing them.
+fn add_response_headers(headers: &mut HeaderMap, added: &[ResponseHeader], replace: bool) {
+    if replace {
+        for header in added {
+            headers.remove(&header.name);
+        }
+    }
+    // Checked before adding any, so a header given twice gets both values
+    let present: Vec<bool> = added
+        .iter()
+        .map(|header| headers.contains_key(&header.name))
         .collect();
-    let body: Value = serde_json::from_slice(req.body())
-        .map_err(|_| "error parsing JSON body of search request")?;
-
-    parse_options(&options, &mut parsed_request)?;
-    parse_body(&body, &mut parsed_request)?;
-
-    let mut result = vec!["Through the fire, to the limit, to the wall, For a chance to be with you, I'd gladly risk it all.", 
-        "You tell me you're gonna play it smart, We're through before we start, But I believe that we've only just begun",
-        "When it's this good, there's no saying no"
-        ];
-
-    if !parsed_request.multi_match.is_empty() {
-        let multi_match: Vec<_> = parsed_request.multi_match.split(' ').collect();
-        result.retain(|result| multi_match.iter().any(|mm| result.contains(mm)));
-    }
-
-    let result = json!({
-        "took": 0,
-        "timed_out": false,
-        "_shards": {
-            "total": 1,
-            "successful": 1,
-            "skipped": 0,
-            "failed": 0,
-        },
-        "hits": {
-            "total": {
-                "value": result.len(),
-                "relation": "eq",
-            },
-            "max_score": 0.0,
-            "hits": result.iter().map(|r| json!({
-                    "_index":"my-first-index",
-                    "_id":"1",
-                    "_version":5,
-                    "_score":0.0,
-                    "_source":
-                        {"Description": r}
-                })).collect::<Vec<_>>()
+    for (header, present) in added.iter().zip(present) {
+        if !present {
+            headers.append(header.name.clone(), header.value.clone());
         }
-    });
+    }
+}
 
-    let mut response = Response::builder();
-    response = response.status(200);
-    if let Some(x_opaque_id) = req.headers().get("x-opaque-id") {
-        response = response.header("x-opaque-id", x_opaque_id);
+/// Parse the `--upstream-auth` header value. It's marked sensitive so it's never printed.
+fn parse_upstream_auth(value: &str) -> Result<HeaderValue, String> {
+    let scheme_ok = match value.split_once(' ') {
+        Some((scheme, credentials)) => {
+            (scheme == "Basic" || scheme == "ApiKey") && !credentials.trim().is_empty()
+        }
+        None => false,
+    };
+    if !scheme_ok {
+        return Err("expected `Basic <credentials>` or `ApiKey <credentials>`".to_string());
     }
-    response = response.header("Content-Type", "application/json; charset=UTF-8");
-    response
-        .body(Full::new(Bytes::from(result.to_string())))
-        .map_err(|_| "error serializing response".to_string())
+    let mut value = HeaderValue::from_str(value)
+        .map_err(|_| "credentials contain invalid header characters".to_string())?;
+    value.set_sensitive(true);
+    Ok(value)
 }
 
-/// Handle incoming request, either by emulating _search endpoint
-/// or sending the request to OpenSearch nodes as a fallback.
-async fn handle_request(
-    out_addr: &SocketAddr,
-    req: Request<Bytes>,
-    stats: Arc<Mutex<Stats>>,
-) -> Result<Response<http_body_util::Full<hyper::body::Bytes>>, hyper::Error> {
-    static SEARCH_ENDPOINT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/([^/]*)/_search$").unwrap());
+/// Counters are atomics, bumped without locking on every request.
+/// Only the logs, the latencies and the per-tenant breakdown are behind mutexes.
+#[derive(Debug, Default)]
+struct Stats {
+    search_queries_success_count: AtomicU64,
+    search_queries_failure_count: AtomicU64,
+    // Part of the search queries above, which were _count ones
+    count_queries_success_count: AtomicU64,
+    nonsearch_passed_through_count: AtomicU64,
+    // Forwarded scroll continuations and clears, apart from the other non-search requests
+    scroll_requests_count: AtomicU64,
+    injected_shard_failures_count: AtomicU64,
+    upstream_errors_count: AtomicU64,
+    // Attempts to forward a request again after OpenSearch couldn't be reached
+    upstream_retries_count: AtomicU64,
+    // Not forwarded because the circuit breaker was open
+    breaker_rejected_count: AtomicU64,
+    // Not emulated and not forwarded because of `--forward-policy`
+    blocked_requests_count: AtomicU64,
+    // Emulated searches answered with an error by `--chaos-error-rate`
+    chaos_errors_count: AtomicU64,
+    // Operations of the forwarded _bulk requests, per action
+    bulk_index_count: AtomicU64,
+    bulk_create_count: AtomicU64,
+    bulk_update_count: AtomicU64,
+    bulk_delete_count: AtomicU64,
+    // Emulated searches served from the cache, and computed because they weren't in it
+    search_cache_hits_count: AtomicU64,
+    search_cache_misses_count: AtomicU64,
+    // Result of the latest background connection check of OpenSearch
+    upstream_reachable: AtomicBool,
+    // Not reset with the stats, it's the state of the proxy
+    upstream_breaker: CircuitBreaker,
+    // Wall-clock time of the emulated searches, and of the forwarded requests
+    search_latency: Latencies,
+    forward_latency: Latencies,
+    // Latest failures only, the oldest are dropped beyond `max_failures`
+    search_queries_failures: Mutex<VecDeque<(String, Bytes)>>,
+    failures_dropped_count: AtomicU64,
+    passthrough_samples: Mutex<VecDeque<(String, Bytes)>>,
+    tenants: Mutex<HashMap<String, TenantStats>>,
+}
 
-    if SEARCH_ENDPOINT.is_match(req.uri().path()) {
-        let res = handle_search_request(&req).await;
-        match res {
-            Ok(res) => {
-                stats.lock().unwrap().search_queries_success_count += 1;
-                return Ok(res);
-            }
-            Err(err) => {
-                let mut stats = stats.lock().unwrap();
-                stats.search_queries_failure_count += 1;
-                stats
-                    .search_queries_failures
-                    .push((err.clone(), req.body().clone()));
-                println!("Error handling search request: {}", err);
-            }
-        }
-    } else {
-        stats.lock().unwrap().nonsearch_passed_through_count += 1;
+/// Histogram of latencies in microseconds, up to a minute: slower ones are counted as a minute.
+/// Recording is an increment behind an uncontended lock, no allocation.
+#[derive(Debug)]
+struct Latencies(Mutex<Histogram<u64>>);
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Latencies(Mutex::new(
+            Histogram::new_with_bounds(1, 60_000_000, 3).unwrap(),
+        ))
+    }
+}
+
+impl Latencies {
+    fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.0.lock().unwrap().saturating_record(micros.max(1));
+    }
+
+    /// Latency in milliseconds at the percentile (0-100), 0 before anything was recorded
+    fn percentile_ms(&self, percentile: f64) -> f64 {
+        self.0.lock().unwrap().value_at_percentile(percentile) as f64 / 1000.0
     }
 
-    forward_request_to_opensearch(out_addr, &req).await
+    fn reset(&self) {
+        self.0.lock().unwrap().reset();
+    }
 }
 
-fn get_queries_failures(stats: Arc<Mutex<Stats>>) -> String {
-    let stats = stats.lock().unwrap();
-    let mut result = "".to_owned();
+/// Percentiles reported for the latencies
+const LATENCY_PERCENTILES: [f64; 3] = [50.0, 95.0, 99.0];
 
-    for (reason, body) in &stats.search_queries_failures {
-        result.push_str(format!("<div class='failure_row'><div class='failure_reason'>{}</div> <div class='failure_body'>{:#?}</div></div>", reason, std::str::from_utf8(body).unwrap_or("")).as_str());
+impl Serialize for Latencies {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let histogram = self.0.lock().unwrap();
+        let mut latencies = serializer.serialize_struct("Latencies", 4)?;
+        latencies.serialize_field("count", &histogram.len())?;
+        for (name, percentile) in ["p50_ms", "p95_ms", "p99_ms"]
+            .into_iter()
+            .zip(LATENCY_PERCENTILES)
+        {
+            let value = histogram.value_at_percentile(percentile) as f64 / 1000.0;
+            latencies.serialize_field(name, &value)?;
+        }
+        latencies.end()
     }
-    result
 }
 
-use axum::{routing::get, Router};
-use tower_http::services::ServeFile;
+/// How many of the latest sampled passthrough requests are kept
+const MAX_PASSTHROUGH_SAMPLES: usize = 100;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Monitoring website
-    let stats1 = Arc::new(Mutex::new(Stats {
-        search_queries_success_count: 0,
-        search_queries_failure_count: 0,
-        nonsearch_passed_through_count: 0,
-        search_queries_failures: Vec::new(),
-    }));
-    let stats2 = stats1.clone();
-    let stats3 = stats1.clone();
-    let stats4 = stats1.clone();
-    let stats5 = stats1.clone();
+#[derive(Debug, Default, Serialize)]
+struct TenantStats {
+    search_queries_success_count: u64,
+    search_queries_failure_count: u64,
+    nonsearch_passed_through_count: u64,
+}
 
-    let app = Router::new()
-        .route(
-            "/search_queries_success_count",
-            get(move || async move {
-                format!("{}", stats1.lock().unwrap().search_queries_success_count)
-            }),
-        )
-        .route(
-            "/search_queries_failure_count",
-            get(move || async move {
-                format!("{}", stats2.lock().unwrap().search_queries_failure_count)
-            }),
-        )
-        .route(
-            "/search_queries_failures",
-            get(move || async move { get_queries_failures(stats3) }),
-        )
-        .route(
-            "/nonsearch_passed_through_count",
-            get(move || async move {
-                format!("{}", stats4.lock().unwrap().nonsearch_passed_through_count)
-            }),
-        )
-        .route_service("/", ServeFile::new("../frontend/index.html"))
-        .route_service("/favicon.ico", ServeFile::new("../frontend/favicon.ico"));
+/// JSON view of the stats for /stats.json: the counters, with only the number
+/// of entries of the failures and samples logs instead of the request bodies
+impl Serialize for Stats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let counters = self.counters();
+        let mut stats = serializer.serialize_struct("Stats", counters.len() + 7)?;
+        for (name, counter) in counters {
+            stats.serialize_field(name, &counter.load(Ordering::Relaxed))?;
+        }
+        stats.serialize_field(
+            "upstream_reachable",
+            &self.upstream_reachable.load(Ordering::Relaxed),
+        )?;
+        stats.serialize_field("upstream_breaker", &self.upstream_breaker)?;
+        stats.serialize_field("search_latency", &self.search_latency)?;
+        stats.serialize_field("forward_latency", &self.forward_latency)?;
+        stats.serialize_field(
+            "search_queries_failures_count",
+            &self.search_queries_failures.lock().unwrap().len(),
+        )?;
+        stats.serialize_field(
+            "passthrough_samples_count",
+            &self.passthrough_samples.lock().unwrap().len(),
+        )?;
+        stats.serialize_field("tenants", &*self.tenants.lock().unwrap())?;
+        stats.end()
+    }
+}
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
-    println!("listening on {}", addr);
-    tokio::task::spawn(async move {
-        hyper_server::bind(addr)
-            .serve(app.into_make_service())
-            .await
-            .unwrap();
-    });
+impl Stats {
+    /// The counters, by name
+    fn counters(&self) -> [(&'static str, &AtomicU64); 18] {
+        [
+            (
+                "search_queries_success_count",
+                &self.search_queries_success_count,
+            ),
+            (
+                "search_queries_failure_count",
+                &self.search_queries_failure_count,
+            ),
+            (
+                "count_queries_success_count",
+                &self.count_queries_success_count,
+            ),
+            (
+                "nonsearch_passed_through_count",
+                &self.nonsearch_passed_through_count,
+            ),
+            ("scroll_requests_count", &self.scroll_requests_count),
+            (
+                "injected_shard_failures_count",
+                &self.injected_shard_failures_count,
+            ),
+            ("upstream_errors_count", &self.upstream_errors_count),
+            ("upstream_retries_count", &self.upstream_retries_count),
+            ("breaker_rejected_count", &self.breaker_rejected_count),
+            ("blocked_requests_count", &self.blocked_requests_count),
+            ("chaos_errors_count", &self.chaos_errors_count),
+            ("bulk_index_count", &self.bulk_index_count),
+            ("bulk_create_count", &self.bulk_create_count),
+            ("bulk_update_count", &self.bulk_update_count),
+            ("bulk_delete_count", &self.bulk_delete_count),
+            ("search_cache_hits_count", &self.search_cache_hits_count),
+            ("search_cache_misses_count", &self.search_cache_misses_count),
+            ("failures_dropped_count", &self.failures_dropped_count),
+        ]
+    }
 
-    // Proxy
-    let in_addr: SocketAddr = ([0, 0, 0, 0], 3000).into();
-    let out_addr: SocketAddr = ([127, 0, 0, 1], 9200).into();
+    /// Zero all the counters and clear the logs, as if the proxy just started
+    fn reset(&self) {
+        for (_, counter) in self.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.search_latency.reset();
+        self.forward_latency.reset();
+        self.search_queries_failures.lock().unwrap().clear();
+        self.passthrough_samples.lock().unwrap().clear();
+        self.tenants.lock().unwrap().clear();
+    }
 
-    let listener = TcpListener::bind(in_addr).await?;
+    /// Update stats of the given tenant, bucketing tenants over the `max_tenants` cap into "other"
+    fn update_tenant(
+        &self,
+        tenant: &str,
+        max_tenants: usize,
+        update: impl FnOnce(&mut TenantStats),
+    ) {
+        let mut tenants = self.tenants.lock().unwrap();
+        let tenant = if tenants.contains_key(tenant) || tenants.len() < max_tenants {
+            tenant
+        } else {
+            "other"
+        };
+        update(tenants.entry(tenant.to_string()).or_default());
+    }
+}
 
-    println!("Listening on http://{}", in_addr);
-    println!("Proxying to http://{}", out_addr);
+/// Why the body of a request couldn't be collected
+enum BodyError {
+    /// Over `--max-body-bytes`
+    TooLarge,
+    Incoming(hyper::Error),
+}
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
+/// Convert a Request with incoming data to a Request with the data streamed in and ready to go,
+/// up to `max_bytes` of data.
+/// Clients sending `Expect: 100-continue` (like curl for bodies over 1 MiB) wait for hyper
+/// to answer the interim 100 Continue, which it does when the body is first read: a request
+/// rejected from its `Content-Length` gets its 413 without the client sending the body.
+async fn request_with_streamed_body(
+    req: Request<Incoming>,
+    max_bytes: usize,
+) -> Result<Request<Bytes>, BodyError> {
+    let (parts, body) = req.into_parts();
+    // Rejected without reading anything when the announced length is over the limit already
+    let content_length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_bytes as u64) {
+        return Err(BodyError::TooLarge);
+    }
+    let body = Limited::new(body, max_bytes)
+        .collect()
+        .await
+        // Errors of the incoming body are passed through as they are, the others are the limit's
+        .map_err(|err| match err.downcast::<hyper::Error>() {
+            Ok(err) => BodyError::Incoming(*err),
+            Err(_) => BodyError::TooLarge,
+        })?
+        .to_bytes();
+    Ok(Request::from_parts(parts, body))
+}
 
-        let stats = stats5.clone();
+/// Mark the credential headers as sensitive, so they're logged as "Sensitive" instead of their value
+fn redact_credentials(headers: &mut HeaderMap) {
+    for name in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE] {
+        if let hyper::header::Entry::Occupied(mut entry) = headers.entry(name) {
+            entry.iter_mut().for_each(|value| value.set_sensitive(true));
+        }
+    }
+}
 
-        let service = service_fn(move |req: hyper::Request<Incoming>| {
-            let stats = stats.clone();
+/// Convert a Request<Bytes> to Request<Full<Bytes>>.
+/// The body was collected, so a chunked request is sent with its actual length instead:
+/// keeping the incoming `Transfer-Encoding` next to it would make OpenSearch reject it.
+/// `Expect: 100-continue` was met when the body was read, it's not passed on.
+fn request_with_full_body(req: &Request<Bytes>) -> Result<Request<Full<Bytes>>, hyper::Error> {
+    let (mut parts, body) = req.clone().into_parts();
+    let chunked = parts.headers.remove(TRANSFER_ENCODING).is_some();
+    parts.headers.remove(EXPECT);
+    // Bodyless requests like most GETs are kept without any length
+    if chunked || !body.is_empty() || parts.headers.contains_key(CONTENT_LENGTH) {
+        parts
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
+    let body = Full::new(body);
+    Ok(Request::from_parts(parts, body))
+}
 
-            async move {
-                println!("-------------------------");
-                let req = request_with_streamed_body(req).await?;
-                println!("Got request: {:#?}", req);
+/// Client for forwarding to the OpenSearch nodes, keeping idle connections (per node) for reuse.
+/// Compared to a new connection per request, this cut the passthrough latency
+/// measured locally (sequential GET /_cat/indices) from ~590us to ~230us.
+struct UpstreamClient {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    upstreams: Upstreams,
+}
 
-                let res = handle_request(&out_addr, req, stats).await?;
-                println!("Sending back: {:#?}", res);
+impl UpstreamClient {
+    fn new(config: &Config) -> Result<Self, String> {
+        Ok(UpstreamClient {
+            http: Client::builder(TokioExecutor::new())
+                .pool_max_idle_per_host(config.upstream_pool_size)
+                .build(upstream_connector(config)?),
+            upstreams: config.upstreams(),
+        })
+    }
+}
 
-                Ok::<Response<http_body_util::Full<hyper::body::Bytes>>, hyper::Error>(res)
-            }
-        });
+/// Failure to forward a request to OpenSearch
+#[derive(Debug)]
+enum ForwardError {
+    /// Reading the incoming request failed
+    Body(hyper::Error),
+    /// Connecting to OpenSearch or sending the request to it failed
+    Upstream(hyper_util::client::legacy::Error),
+    InvalidUri(http::uri::InvalidUri),
+    /// OpenSearch didn't start responding in time
+    Timeout(Duration),
+}
 
-        tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
-                println!("Failed to serve the connection: {:?}", err);
+impl std::fmt::Display for ForwardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForwardError::Body(err) => write!(f, "error transferring body: {}", err),
+            // The cause (like "tcp connect error") is more telling than the error itself
+            ForwardError::Upstream(err) => match std::error::Error::source(err) {
+                Some(source) => write!(f, "error forwarding to OpenSearch: {}", source),
+                None => write!(f, "error forwarding to OpenSearch: {}", err),
+            },
+            ForwardError::InvalidUri(err) => write!(f, "invalid forwarded URI: {}", err),
+            ForwardError::Timeout(timeout) => {
+                write!(f, "OpenSearch didn't respond within {:?}", timeout)
             }
-        });
+        }
+    }
+}
+
+impl std::error::Error for ForwardError {}
+
+impl From<hyper::Error> for ForwardError {
+    fn from(err: hyper::Error) -> Self {
+        ForwardError::Body(err)
+    }
+}
+
+impl From<hyper_util::client::legacy::Error> for ForwardError {
+    fn from(err: hyper_util::client::legacy::Error) -> Self {
+        ForwardError::Upstream(err)
+    }
+}
+
+impl From<http::uri::InvalidUri> for ForwardError {
+    fn from(err: http::uri::InvalidUri) -> Self {
+        ForwardError::InvalidUri(err)
+    }
+}
+
+/// Body of responses sent back to the client, either emulated or forwarded
+type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+/// Body of a response fully built in memory
+fn full_body(body: Bytes) -> ProxyBody {
+    Full::new(body).map_err(|never| match never {}).boxed()
+}
+
+/// Convert a Response with incoming data to a Response streaming the data to the client
+/// as it arrives, without buffering large responses (big _search results, _bulk, ...).
+/// Trailers sent by OpenSearch (with chunked transfer encoding) are kept and sent after the data.
+fn response_with_streamed_body(res: Response<Incoming>) -> Response<ProxyBody> {
+    res.map(|body| body.boxed())
+}
+
+/// Apply path rewrite rules (in order) to the request about to be forwarded
+fn rewrite_path(req: Request<Bytes>, rules: &[PathRewrite]) -> Request<Bytes> {
+    let mut path = req.uri().path().to_string();
+    for rule in rules {
+        if let Some(rest) = path.strip_prefix(&rule.from) {
+            path = format!("{}{}", rule.to, rest);
+        }
+    }
+    if path == req.uri().path() {
+        return req;
+    }
+
+    let (mut parts, body) = req.into_parts();
+    let path_and_query = match parts.uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut uri = parts.uri.clone().into_parts();
+    match path_and_query.parse() {
+        Ok(path_and_query) => uri.path_and_query = Some(path_and_query),
+        Err(err) => warn!("invalid rewritten path {}: {}", path_and_query, err),
+    }
+    if let Ok(rewritten) = http::Uri::from_parts(uri) {
+        parts.uri = rewritten;
+    }
+    Request::from_parts(parts, body)
+}
+
+/// Retries of the forwarded requests failing to reach OpenSearch, with exponential backoff
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+}
+
+/// Whether sending the request twice is harmless: reads, and searches even when POSTed.
+/// Scroll continuations are searches moving a cursor, so they aren't.
+fn is_retryable(req: &Request<Bytes>) -> bool {
+    match *req.method() {
+        Method::GET | Method::HEAD => true,
+        Method::POST => ["/_search", "/_msearch", "/_count"]
+            .iter()
+            .any(|endpoint| req.uri().path().ends_with(endpoint)),
+        _ => false,
+    }
+}
+
+/// We don't know how to handle this request, so let's forward it to OpenSearch instead.
+/// It goes to the next node, and is retried per `retry` (on the next nodes) when it's
+/// retryable and OpenSearch couldn't be reached. Error statuses from OpenSearch and
+/// timeouts aren't retried.
+async fn forward_request_to_opensearch(
+    client: &UpstreamClient,
+    req: &Request<Bytes>,
+    timeout: Duration,
+    retry: RetryPolicy,
+    retries_count: &AtomicU64,
+) -> Result<Response<ProxyBody>, ForwardError> {
+    let retryable = is_retryable(req);
+    let mut attempt = 0;
+    loop {
+        // The body is shared by the attempts, not copied
+        let node = client.upstreams.pick();
+        let res = send_to_opensearch(&client.http, &node.url, req, timeout).await;
+        match &res {
+            Ok(_) => client.upstreams.record_success(node),
+            Err(ForwardError::Upstream(err)) if err.is_connect() => {
+                client.upstreams.record_failure(node)
+            }
+            Err(_) => {}
+        }
+        match res {
+            Err(err @ ForwardError::Upstream(_)) if retryable && attempt < retry.max_retries => {
+                debug!("retrying after forwarding to {} failed: {}", node.addr, err);
+                retries_count.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(retry.backoff.saturating_mul(1 << attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Waiting for the response headers is bounded by `timeout`. When it fires, the request future
+/// is dropped, which closes its connection instead of returning it to the pool.
+/// The body is then streamed to the client, however long it takes.
+async fn send_to_opensearch(
+    client: &Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    upstream_url: &str,
+    req: &Request<Bytes>,
+    timeout: Duration,
+) -> Result<Response<ProxyBody>, ForwardError> {
+    let mut req = request_with_full_body(req)?;
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    *req.uri_mut() = format!("{}{}", upstream_url, path_and_query).parse()?;
+    // OpenSearch is reached over HTTP/1.1, whatever the client speaks to the proxy
+    *req.version_mut() = http::Version::HTTP_11;
+
+    match tokio::time::timeout(timeout, client.request(req)).await {
+        Ok(res) => Ok(response_with_streamed_body(res?)),
+        Err(_) => Err(ForwardError::Timeout(timeout)),
+    }
+}
+
+/// Emulation of a search-like endpoint (_search, _count, _msearch)
+#[derive(Clone, Copy)]
+struct SearchEndpoint {
+    name: &'static str,
+    run: fn(&DocumentStore, &SearchInput, &SearchSettings) -> Result<Value, SearchError>,
+    /// Canonical form of the requests, keying their cached responses
+    normalize: fn(&SearchInput, &SearchSettings) -> Result<String, SearchError>,
+}
+
+const SEARCH: SearchEndpoint = SearchEndpoint {
+    name: "_search",
+    run: search,
+    normalize: normalized_search,
+};
+
+const COUNT: SearchEndpoint = SearchEndpoint {
+    name: "_count",
+    run: count,
+    normalize: normalized_count,
+};
+
+const MSEARCH: SearchEndpoint = SearchEndpoint {
+    name: "_msearch",
+    run: msearch,
+    normalize: normalized_msearch,
+};
+
+/// Try to handle request to a search-like endpoint. If we can handle it,
+/// return the results from the store (only the documents of `index`), else return an error.
+/// `body` is the decompressed request body, the response is compressed when the client accepts it.
+/// Equivalent searches are served from the cache, telling whether this one was.
+#[allow(clippy::too_many_arguments)]
+async fn handle_search_request(
+    req: &Request<Bytes>,
+    body: &[u8],
+    index: Option<&str>,
+    store: &DocumentStore,
+    settings: &SearchSettings,
+    endpoint: SearchEndpoint,
+    inject_shard_failure: bool,
+    cache: &SearchCache,
+) -> Result<(Response<ProxyBody>, Option<CacheLookup>), SearchError> {
+    let input = SearchInput {
+        index,
+        query: req.uri().query().unwrap_or(""),
+        body,
+    };
+    // Responses with an injected failure are neither served from the cache nor cached.
+    // Neither are requests which aren't emulated, the endpoint tells why below.
+    let key = (!inject_shard_failure)
+        .then(|| (endpoint.normalize)(&input, settings).ok())
+        .flatten()
+        .map(|normalized| format!("{}\n{}", endpoint.name, normalized));
+    if let Some(key) = &key {
+        // Served as it was computed, `took` included
+        if let Some(cached) = cache.get(store.generation(), key) {
+            let res = emulated_response(req, "application/json; charset=UTF-8", cached)
+                .map_err(SearchError::Unsupported)?;
+            return Ok((res, Some(CacheLookup::Hit)));
+        }
+    }
+    let mut result = (endpoint.run)(store, &input, settings)?;
+    if inject_shard_failure {
+        inject_failed_shard(&mut result);
+    }
+    let response = Bytes::from(result.to_string());
+    let lookup = key.is_some().then_some(CacheLookup::Miss);
+    // Partial results would stay partial, the next search may not time out
+    if let Some(key) = key.filter(|_| !timed_out(&result)) {
+        cache.insert(store.generation(), key, response.clone());
+    }
+    let res = emulated_response(req, "application/json; charset=UTF-8", response)
+        .map_err(SearchError::Unsupported)?;
+    Ok((res, lookup))
+}
+
+/// Whether an emulated search was served from the cache, None when it wasn't looked up
+enum CacheLookup {
+    Hit,
+    Miss,
+}
+
+/// Whether the search, or any search of a _msearch, timed out with partial results
+fn timed_out(result: &Value) -> bool {
+    result["timed_out"] == true
+        || result["responses"]
+            .as_array()
+            .is_some_and(|responses| responses.iter().any(timed_out))
+}
+
+/// Emulation of a GET endpoint without a body, describing the cluster (_cat/indices, _cluster/health)
+/// or reading from the store (_doc)
+type MetadataEndpoint =
+    fn(&Request<Bytes>, &DocumentStore, &Config) -> Result<Response<ProxyBody>, String>;
+
+/// Try to handle a _cat/indices request, listing the indices of the document store
+fn handle_cat_indices(
+    req: &Request<Bytes>,
+    store: &DocumentStore,
+    _config: &Config,
+) -> Result<Response<ProxyBody>, String> {
+    let result = cat_indices(store, req.uri().query().unwrap_or(""))?;
+    emulated_response(req, result.content_type, Bytes::from(result.body))
+}
+
+/// Handle a _cluster/health request, reporting a healthy cluster whatever the state of OpenSearch
+fn handle_cluster_health(
+    req: &Request<Bytes>,
+    store: &DocumentStore,
+    _config: &Config,
+) -> Result<Response<ProxyBody>, String> {
+    emulated_response(
+        req,
+        "application/json; charset=UTF-8",
+        Bytes::from(cluster_health(store).to_string()),
+    )
+}
+
+/// Handle a `GET /` request, the version handshake of the clients
+fn handle_root_info(
+    req: &Request<Bytes>,
+    _store: &DocumentStore,
+    config: &Config,
+) -> Result<Response<ProxyBody>, String> {
+    emulated_response(
+        req,
+        "application/json; charset=UTF-8",
+        Bytes::from(root_info(&config.root_info_version).to_string()),
+    )
+}
+
+/// Try to handle a `GET /<index>/_doc/<id>` request, with a 404 for a missing document
+fn handle_get_document(
+    req: &Request<Bytes>,
+    store: &DocumentStore,
+    _config: &Config,
+) -> Result<Response<ProxyBody>, String> {
+    if let Some(query) = req.uri().query() {
+        return Err(format!("unimplemented get document URL options: {}", query));
+    }
+    let path = req.uri().path();
+    let Some((index, id)) = path[1..].split_once("/_doc/") else {
+        return Err(format!("unimplemented get document path: {}", path));
+    };
+    let (index, id) = (
+        percent_decode_str(index).decode_utf8_lossy(),
+        percent_decode_str(id).decode_utf8_lossy(),
+    );
+    let (found, result) = get_document(store, &index, &id)?;
+    let mut res = emulated_response(
+        req,
+        "application/json; charset=UTF-8",
+        Bytes::from(result.to_string()),
+    )?;
+    if !found {
+        *res.status_mut() = StatusCode::NOT_FOUND;
+    }
+    Ok(res)
+}
+
+/// Successful response to an emulated request, echoing its `x-opaque-id`
+/// and compressed when the client accepts it
+fn emulated_response(
+    req: &Request<Bytes>,
+    content_type: &str,
+    mut body: Bytes,
+) -> Result<Response<ProxyBody>, String> {
+    let mut response = Response::builder();
+    response = response.status(200);
+    if let Some(x_opaque_id) = req.headers().get("x-opaque-id") {
+        response = response.header("x-opaque-id", x_opaque_id);
+    }
+    response = response.header("Content-Type", content_type);
+    if let Some(encoding) = response_encoding(req.headers()) {
+        response = response
+            .header("Content-Encoding", encoding.name())
+            .header("Vary", "Accept-Encoding");
+        body = Bytes::from(encode(encoding, &body));
+    }
+    response
+        .body(full_body(body))
+        .map_err(|_| "error serializing response".to_string())
+}
+
+/// Report one more (failed) shard in the response, keeping the hits we computed
+fn inject_failed_shard(result: &mut Value) {
+    // Each of the _msearch responses has its own shards
+    if let Some(Value::Array(responses)) = result.get_mut("responses") {
+        responses.iter_mut().for_each(inject_failed_shard);
+        return;
+    }
+    let Some(shards) = result.get_mut("_shards") else {
+        return;
+    };
+    shards["total"] = json!(shards["total"].as_u64().unwrap_or(0) + 1);
+    shards["failed"] = json!(shards["failed"].as_u64().unwrap_or(0) + 1);
+    shards["failures"] = json!([{
+        "shard": 1,
+        "index": DEFAULT_INDEX,
+        "node": "tinyq",
+        "reason": {
+            "type": "node_not_connected_exception",
+            "reason": "[tinyq] injected shard failure",
+        },
+    }]);
+}
+
+/// Latency and errors injected into the emulated searches, for resilience testing
+struct Chaos {
+    latency: Duration,
+    error_rate: f64,
+    // Seeded with `--chaos-seed` when given, so the same requests get the same errors
+    rng: Mutex<StdRng>,
+}
+
+impl Chaos {
+    fn new(config: &Config) -> Self {
+        let rng = match config.chaos_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Chaos {
+            latency: Duration::from_millis(config.chaos_latency_ms),
+            error_rate: config.chaos_error_rate,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Whether the emulated search gets an error instead of its response
+    fn inject_error(&self) -> bool {
+        self.error_rate > 0.0 && self.rng.lock().unwrap().gen::<f64>() < self.error_rate
+    }
+}
+
+/// Response replacing an emulated search with `--chaos-error-rate` (503),
+/// like OpenSearch without enough available shards
+fn chaos_error_response() -> Response<ProxyBody> {
+    let reason = "all shards failed (injected by chaos mode)";
+    let body = json!({
+        "error": {
+            "root_cause": [],
+            "type": "search_phase_execution_exception",
+            "reason": reason,
+            "phase": "query",
+            "grouped": true,
+            "failed_shards": [],
+        },
+        "status": 503,
+    });
+    Response::builder()
+        .status(503)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Why the request was emulated or forwarded to OpenSearch
+enum Decision {
+    Emulated,
+    /// Endpoint we don't try to emulate at all
+    NotEmulatedEndpoint,
+    /// Endpoint we emulate, but this particular request isn't supported
+    Unsupported(String),
+    /// Scroll continuation or clear, always forwarded
+    Scroll,
+    /// Emulated endpoint called without the `--upstream-auth` credentials, or with others
+    Unauthenticated,
+    /// Body of an emulated endpoint isn't valid JSON, OpenSearch would reject it too
+    Malformed(String),
+    /// Not emulated, and `--forward-policy` doesn't allow forwarding it
+    Blocked(ForwardPolicy),
+    /// Emulated search answered with an error by `--chaos-error-rate`
+    ChaosError,
+    /// CORS preflight request, answered when `--cors-origin` is set
+    CorsPreflight,
+    /// Health check of the proxy itself, see `HEALTH_PATH`
+    HealthCheck,
+    /// Reload of the documents file, see `RELOAD_PATH`
+    Reload,
+    /// To be forwarded, but OpenSearch keeps failing and the circuit breaker is open
+    BreakerOpen,
+    /// Emulated endpoint, but this particular request isn't supported and `--standalone`
+    /// leaves nowhere to forward it
+    NotImplemented(String),
+    /// Body over `--max-body-bytes`, neither emulated nor forwarded
+    BodyTooLarge,
+}
+
+impl Decision {
+    /// Outcome, as in the access log: emulated, forwarded, rejected or blocked
+    fn outcome(&self) -> &'static str {
+        match self {
+            Decision::Emulated
+            | Decision::CorsPreflight
+            | Decision::HealthCheck
+            | Decision::Reload => "emulated",
+            Decision::NotEmulatedEndpoint | Decision::Unsupported(_) | Decision::Scroll => {
+                "forwarded"
+            }
+            Decision::Unauthenticated
+            | Decision::Malformed(_)
+            | Decision::ChaosError
+            | Decision::NotImplemented(_)
+            | Decision::BodyTooLarge
+            | Decision::BreakerOpen => "rejected",
+            Decision::Blocked(_) => "blocked",
+        }
+    }
+}
+
+/// Outcome of the decision, attached to the response for the access log
+#[derive(Clone, Copy)]
+struct Outcome(&'static str);
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Emulated => write!(f, "outcome=emulated"),
+            Decision::NotEmulatedEndpoint => {
+                write!(f, "outcome=forwarded reason=\"endpoint not emulated\"")
+            }
+            Decision::Unsupported(reason) => {
+                write!(f, "outcome=forwarded reason={:?}", reason)
+            }
+            Decision::Scroll => write!(f, "outcome=forwarded reason=\"scroll\""),
+            Decision::Unauthenticated => {
+                write!(
+                    f,
+                    "outcome=rejected reason=\"missing or wrong credentials\""
+                )
+            }
+            Decision::Malformed(reason) | Decision::NotImplemented(reason) => {
+                write!(f, "outcome=rejected reason={:?}", reason)
+            }
+            Decision::Blocked(policy) => {
+                write!(f, "outcome=blocked reason=\"forward policy {:?}\"", policy)
+            }
+            Decision::ChaosError => write!(f, "outcome=rejected reason=\"chaos error\""),
+            Decision::CorsPreflight => write!(f, "outcome=emulated reason=\"CORS preflight\""),
+            Decision::HealthCheck => write!(f, "outcome=emulated reason=\"health check\""),
+            Decision::Reload => write!(f, "outcome=emulated reason=\"documents reload\""),
+            Decision::BreakerOpen => {
+                write!(f, "outcome=rejected reason=\"circuit breaker open\"")
+            }
+            Decision::BodyTooLarge => write!(f, "outcome=rejected reason=\"body too large\""),
+        }
+    }
+}
+
+/// Log a single line describing the emulate/forward decision for the request,
+/// the method and path come from the request span
+fn log_decision(decision: &Decision) {
+    info!("decision {}", decision);
+}
+
+/// Log the decision for a request answered by the proxy itself, see `explain_decision`
+fn decided(
+    mut res: Response<ProxyBody>,
+    decision: &Decision,
+    explain: bool,
+) -> Response<ProxyBody> {
+    log_decision(decision);
+    res.extensions_mut().insert(Outcome(decision.outcome()));
+    if explain {
+        explain_decision(&mut res, decision);
+    }
+    res
+}
+
+/// Describe the decision in the `X-TinyQ-Decision` response header, as logged,
+/// for clients sending `X-TinyQ-Explain: true`
+fn explain_decision(res: &mut Response<ProxyBody>, decision: &Decision) {
+    // Header values can't have control or non-ASCII characters, which parse errors may quote
+    let description: String = decision
+        .to_string()
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect();
+    if let Ok(description) = HeaderValue::from_str(&description) {
+        res.headers_mut().insert("x-tinyq-decision", description);
+    }
+}
+
+/// Handle incoming request, either by emulating _search endpoint
+/// or sending the request to OpenSearch nodes as a fallback.
+async fn handle_request(
+    client: &UpstreamClient,
+    req: Request<Bytes>,
+    stats: Arc<Stats>,
+    current_store: Arc<CurrentStore>,
+    config: Arc<Config>,
+    chaos: Arc<Chaos>,
+    cache: Arc<SearchCache>,
+) -> Response<ProxyBody> {
+    // With or without an index in the path, searching all the indices without one
+    static SEARCH_ENDPOINT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^(?:/([^/]*))?/_search$").unwrap());
+    static COUNT_ENDPOINT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/([^/]*)/_count$").unwrap());
+    static MSEARCH_ENDPOINT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^(?:/([^/]*))?/_msearch$").unwrap());
+    static CAT_INDICES_ENDPOINT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^/_cat/indices$").unwrap());
+    static CLUSTER_HEALTH_ENDPOINT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^/_cluster/health$").unwrap());
+    static DOC_ENDPOINT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^/([^/]*)/_doc/(.+)$").unwrap());
+    static BULK_ENDPOINT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?:/[^/]*)?/_bulk$").unwrap());
+    // With the scroll id in the body, or in the path like older clients do
+    static SCROLL_ENDPOINT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^/_search/scroll(?:/[^/]+)?$").unwrap());
+
+    let start = Instant::now();
+    // Debugging aid for onboarding query shapes, the body of the response stays the same
+    let explain = req
+        .headers()
+        .get("x-tinyq-explain")
+        .is_some_and(|explain| explain == "true");
+    let tenant = req
+        .headers()
+        .get(&config.tenant_header)
+        .and_then(|tenant| tenant.to_str().ok())
+        .unwrap_or("none");
+
+    let path = req.uri().path();
+    // Index expression of the path, searching all the indices without one
+    let mut index = None;
+    let endpoint: Option<SearchEndpoint> = if let Some(captures) = SEARCH_ENDPOINT.captures(path) {
+        index = captures.get(1);
+        Some(SEARCH)
+    } else if let Some(captures) = COUNT_ENDPOINT.captures(path) {
+        index = captures.get(1);
+        Some(COUNT)
+    } else if let Some(captures) = MSEARCH_ENDPOINT.captures(path) {
+        index = captures.get(1);
+        Some(MSEARCH)
+    } else {
+        None
+    };
+
+    let metadata_endpoint: Option<MetadataEndpoint> = if req.method() != Method::GET {
+        None
+    } else if CAT_INDICES_ENDPOINT.is_match(path) {
+        Some(handle_cat_indices)
+    } else if config.emulate_cluster_health && CLUSTER_HEALTH_ENDPOINT.is_match(path) {
+        Some(handle_cluster_health)
+    } else if config.emulate_root_info && path == "/" {
+        Some(handle_root_info)
+    } else if DOC_ENDPOINT.is_match(path) {
+        Some(handle_get_document)
+    } else {
+        None
+    };
+
+    // Reserved for the proxy, never emulated nor forwarded
+    if path == HEALTH_PATH {
+        return decided(
+            health_response(req.method(), &stats),
+            &Decision::HealthCheck,
+            explain,
+        );
+    }
+    if path == RELOAD_PATH {
+        return decided(
+            reload_response(req.method(), &current_store, &config).await,
+            &Decision::Reload,
+            explain,
+        );
+    }
+    // The whole request is handled with the store it started with, even if reloaded meanwhile
+    let store = current_store.get();
+    // Before the credentials check, browsers never send them in preflight requests
+    if config.cors_origin.is_some()
+        && req.method() == Method::OPTIONS
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return decided(
+            cors_preflight_response(&req),
+            &Decision::CorsPreflight,
+            explain,
+        );
+    }
+
+    let decision;
+    // Forwarded request which isn't a search, counted once it's allowed to be forwarded
+    let mut nonsearch = false;
+    // Emulated endpoints answer without OpenSearch checking the credentials, so they're
+    // compared to the configured ones. Forwarded requests may still use others.
+    if (endpoint.is_some() || metadata_endpoint.is_some())
+        && config
+            .upstream_auth
+            .as_ref()
+            .is_some_and(|auth| req.headers().get(AUTHORIZATION) != Some(auth))
+    {
+        let given = req.headers().contains_key(AUTHORIZATION);
+        return decided(
+            unauthenticated_response(req.uri().path(), given),
+            &Decision::Unauthenticated,
+            explain,
+        );
+    }
+    if let Some(metadata_endpoint) = metadata_endpoint {
+        match metadata_endpoint(&req, &store, &config) {
+            Ok(res) => {
+                return decided(res, &Decision::Emulated, explain);
+            }
+            Err(err) => {
+                nonsearch = true;
+                decision = Decision::Unsupported(err);
+            }
+        }
+    } else if let Some(endpoint) = endpoint {
+        // Only _msearch takes ndjson, a body sent as such to the others is a client mistake
+        if is_ndjson(req.headers()) && !MSEARCH_ENDPOINT.is_match(path) {
+            return decided(
+                ndjson_body_response(path),
+                &Decision::Malformed("ndjson body, expected a single JSON object".to_string()),
+                explain,
+            );
+        }
+        let inject_shard_failure = config.inject_shard_failures > 0.0
+            && rand::random::<f64>() < config.inject_shard_failures;
+        let res = match decode_request_body(req.headers(), req.body(), config.max_body_bytes) {
+            Ok(body) => {
+                handle_search_request(
+                    &req,
+                    &body,
+                    index.map(|index| index.as_str()),
+                    &store,
+                    &config.search_settings(),
+                    endpoint,
+                    inject_shard_failure,
+                    &cache,
+                )
+                .await
+            }
+            Err(DecodeError::TooLarge) => {
+                return decided(
+                    body_too_large_response(config.max_body_bytes),
+                    &Decision::BodyTooLarge,
+                    explain,
+                );
+            }
+            Err(DecodeError::Invalid(reason)) => Err(SearchError::Unsupported(reason)),
+        };
+        match res {
+            Ok((res, lookup)) => {
+                if !chaos.latency.is_zero() {
+                    tokio::time::sleep(chaos.latency).await;
+                }
+                if chaos.inject_error() {
+                    stats.chaos_errors_count.fetch_add(1, Ordering::Relaxed);
+                    return decided(chaos_error_response(), &Decision::ChaosError, explain);
+                }
+                stats
+                    .search_queries_success_count
+                    .fetch_add(1, Ordering::Relaxed);
+                if COUNT_ENDPOINT.is_match(req.uri().path()) {
+                    stats
+                        .count_queries_success_count
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                if inject_shard_failure {
+                    stats
+                        .injected_shard_failures_count
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                match lookup {
+                    Some(CacheLookup::Hit) => {
+                        stats
+                            .search_cache_hits_count
+                            .fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(CacheLookup::Miss) => {
+                        stats
+                            .search_cache_misses_count
+                            .fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => {}
+                }
+                stats.update_tenant(tenant, config.max_tenants, |tenant| {
+                    tenant.search_queries_success_count += 1
+                });
+                stats.search_latency.record(start.elapsed());
+                return decided(res, &Decision::Emulated, explain);
+            }
+            Err(SearchError::Malformed(err)) => {
+                return decided(
+                    malformed_body_response(&err),
+                    &Decision::Malformed(err.to_string()),
+                    explain,
+                );
+            }
+            Err(SearchError::Unsupported(err)) => {
+                stats
+                    .search_queries_failure_count
+                    .fetch_add(1, Ordering::Relaxed);
+                stats.update_tenant(tenant, config.max_tenants, |tenant| {
+                    tenant.search_queries_failure_count += 1
+                });
+                let mut failures = stats.search_queries_failures.lock().unwrap();
+                failures.push_back((err.clone(), req.body().clone()));
+                while failures.len() > config.max_failures {
+                    failures.pop_front();
+                    stats.failures_dropped_count.fetch_add(1, Ordering::Relaxed);
+                }
+                debug!("error handling search request: {}", err);
+                decision = Decision::Unsupported(err);
+            }
+        }
+    } else if SCROLL_ENDPOINT.is_match(path) {
+        // Continues a scroll context only OpenSearch has
+        decision = Decision::Scroll;
+    } else {
+        nonsearch = true;
+        decision = Decision::NotEmulatedEndpoint;
+    }
+
+    if let Some(rejection) = config
+        .forward_policy()
+        .rejection(&config.forward_allow, path)
+    {
+        if let (true, Decision::Unsupported(err)) = (config.standalone, &decision) {
+            // Nowhere to forward to, tell what isn't supported instead
+            return decided(
+                Rejection::NotImplemented(err.clone()).response(&req),
+                &Decision::NotImplemented(err.clone()),
+                explain,
+            );
+        }
+        stats.blocked_requests_count.fetch_add(1, Ordering::Relaxed);
+        return decided(
+            rejection.response(&req),
+            &Decision::Blocked(config.forward_policy()),
+            explain,
+        );
+    }
+    if !stats.upstream_breaker.allow(config.upstream_breaker()) {
+        stats.breaker_rejected_count.fetch_add(1, Ordering::Relaxed);
+        return decided(breaker_open_response(), &Decision::BreakerOpen, explain);
+    }
+    if nonsearch {
+        stats
+            .nonsearch_passed_through_count
+            .fetch_add(1, Ordering::Relaxed);
+        stats.update_tenant(tenant, config.max_tenants, |tenant| {
+            tenant.nonsearch_passed_through_count += 1
+        });
+        if BULK_ENDPOINT.is_match(path) {
+            count_bulk_request(&req, config.max_body_bytes, &stats);
+        }
+    }
+    if let Decision::Scroll = decision {
+        stats.scroll_requests_count.fetch_add(1, Ordering::Relaxed);
+    }
+    log_decision(&decision);
+
+    if config.sample_passthrough > 0.0 && rand::random::<f64>() < config.sample_passthrough {
+        let reason = match &decision {
+            Decision::Unsupported(err) => format!("{} {} - {}", req.method(), req.uri(), err),
+            _ => format!("{} {}", req.method(), req.uri()),
+        };
+        let mut samples = stats.passthrough_samples.lock().unwrap();
+        if samples.len() >= MAX_PASSTHROUGH_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((reason, req.body().clone()));
+    }
+
+    let mut req = rewrite_path(req, &config.path_rewrite);
+    if let Some(auth) = &config.upstream_auth {
+        req.headers_mut()
+            .entry(AUTHORIZATION)
+            .or_insert_with(|| auth.clone());
+    }
+    let forward_start = Instant::now();
+    let mut res = match forward_request_to_opensearch(
+        client,
+        &req,
+        config.upstream_timeout(),
+        config.upstream_retry(),
+        &stats.upstream_retries_count,
+    )
+    .await
+    {
+        // Until the response headers, the body is streamed to the client afterwards
+        Ok(res) => {
+            stats.forward_latency.record(forward_start.elapsed());
+            stats.upstream_breaker.record_success();
+            res
+        }
+        Err(err) => {
+            warn!("forwarding failed: {}", err);
+            stats.upstream_errors_count.fetch_add(1, Ordering::Relaxed);
+            if let ForwardError::Upstream(_) | ForwardError::Timeout(_) = err {
+                stats
+                    .upstream_breaker
+                    .record_failure(config.upstream_breaker());
+            }
+            upstream_error_response(&err)
+        }
+    };
+    // Like in the emulated responses, unless OpenSearch already echoed it
+    if let Some(x_opaque_id) = req.headers().get("x-opaque-id") {
+        res.headers_mut()
+            .entry("x-opaque-id")
+            .or_insert_with(|| x_opaque_id.clone());
+    }
+    res.extensions_mut().insert(Outcome(decision.outcome()));
+    if explain {
+        explain_decision(&mut res, &decision);
+    }
+    res
+}
+
+/// Response telling the client that OpenSearch couldn't be reached (502) or timed out (504),
+/// in the OpenSearch error format
+fn upstream_error_response(err: &ForwardError) -> Response<ProxyBody> {
+    let (status, error_type) = match err {
+        ForwardError::Timeout(_) => (504, "upstream_timeout"),
+        _ => (502, "upstream_unavailable"),
+    };
+    let body = json!({
+        "error": {
+            "type": error_type,
+            "reason": err.to_string(),
+        },
+        "status": status,
+    });
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Response to a request which would be forwarded while the circuit breaker is open (503),
+/// in the OpenSearch error format
+fn breaker_open_response() -> Response<ProxyBody> {
+    let reason = "OpenSearch keeps failing, requests aren't forwarded to it until it recovers";
+    let body = json!({
+        "error": {
+            "type": "upstream_circuit_open",
+            "reason": reason,
+        },
+        "status": 503,
+    });
+    Response::builder()
+        .status(503)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Path of the health check of the proxy port, for load balancers
+const HEALTH_PATH: &str = "/_tinyq/health";
+
+/// Response to the health check: the proxy is up, whether OpenSearch is reachable
+/// is only reported, as of the latest background check
+fn health_response(method: &Method, stats: &Stats) -> Response<ProxyBody> {
+    if method != Method::GET && method != Method::HEAD {
+        return Response::builder()
+            .status(405)
+            .header("Allow", "GET, HEAD")
+            .body(full_body(Bytes::new()))
+            .unwrap();
+    }
+    let body = json!({
+        "status": "ok",
+        "upstream_reachable": stats.upstream_reachable.load(Ordering::Relaxed),
+    });
+    Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Path reloading the documents file on the proxy port, with `--allow-reload`
+const RELOAD_PATH: &str = "/_tinyq/reload";
+
+/// Document store, replaced as a whole by a reload
+struct CurrentStore(RwLock<Arc<DocumentStore>>);
+
+impl CurrentStore {
+    fn new(store: DocumentStore) -> Self {
+        CurrentStore(RwLock::new(Arc::new(store)))
+    }
+
+    fn get(&self) -> Arc<DocumentStore> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, store: DocumentStore) {
+        *self.0.write().unwrap() = Arc::new(store);
+    }
+}
+
+/// Reload the documents file, answering the new number of documents. On failure the
+/// documents served so far are kept. The store generation changes, so cached searches
+/// of the previous documents aren't served anymore.
+async fn reload_response(
+    method: &Method,
+    current_store: &CurrentStore,
+    config: &Config,
+) -> Response<ProxyBody> {
+    if method != Method::POST {
+        return Response::builder()
+            .status(405)
+            .header("Allow", "POST")
+            .body(full_body(Bytes::new()))
+            .unwrap();
+    }
+    let (status, body) = match (&config.documents, config.allow_reload) {
+        (Some(path), true) => {
+            let path = path.clone();
+            // Reading and indexing a big file shouldn't hold up the other requests
+            let loaded = tokio::task::spawn_blocking(move || DocumentStore::load(&path))
+                .await
+                .unwrap_or_else(|err| Err(format!("reload didn't complete: {}", err)));
+            match loaded {
+                Ok(store) => {
+                    let count = store.documents().len();
+                    current_store.replace(store);
+                    info!("reloaded {} documents", count);
+                    (200, json!({ "documents_count": count }))
+                }
+                Err(err) => {
+                    warn!("reloading documents failed: {}", err);
+                    (
+                        500,
+                        json!({
+                            "error": {"type": "reload_failed", "reason": err},
+                            "status": 500,
+                        }),
+                    )
+                }
+            }
+        }
+        _ => (
+            403,
+            json!({
+                "error": {
+                    "type": "reload_disabled",
+                    "reason": "reloading the documents requires --allow-reload",
+                },
+                "status": 403,
+            }),
+        ),
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Response to a CORS preflight request, allowing any method and the headers asked for.
+/// The allowed origin is added along with the other responses.
+fn cors_preflight_response(req: &Request<Bytes>) -> Response<ProxyBody> {
+    let mut res = Response::builder()
+        .status(204)
+        .header(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            "GET, POST, PUT, DELETE, HEAD, OPTIONS",
+        )
+        .header(ACCESS_CONTROL_MAX_AGE, "86400");
+    if let Some(headers) = req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS) {
+        res = res.header(ACCESS_CONTROL_ALLOW_HEADERS, headers);
+    }
+    res.body(full_body(Bytes::new())).unwrap()
+}
+
+/// Response rejecting a request with a body over `--max-body-bytes` (413),
+/// in the OpenSearch error format
+fn body_too_large_response(max_bytes: usize) -> Response<ProxyBody> {
+    let reason = format!(
+        "request body is larger than the maximum of {} bytes",
+        max_bytes
+    );
+    let body = json!({
+        "error": {
+            "root_cause": [{"type": "content_too_long_exception", "reason": reason}],
+            "type": "content_too_long_exception",
+            "reason": reason,
+        },
+        "status": 413,
+    });
+    Response::builder()
+        .status(413)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Whether the request body is declared as ndjson. Bodies without a `Content-Type`,
+/// or with another one, are parsed as JSON.
+fn is_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get("content-type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|media_type| {
+            media_type
+                .trim()
+                .eq_ignore_ascii_case("application/x-ndjson")
+        })
+}
+
+/// Response rejecting an ndjson body sent to an endpoint taking a single JSON object (400),
+/// in the OpenSearch error format
+fn ndjson_body_response(path: &str) -> Response<ProxyBody> {
+    let reason = format!(
+        "[{}] takes a single JSON object as body, not ndjson (Content-Type application/x-ndjson): \
+         several searches are sent to _msearch",
+        path
+    );
+    let body = json!({
+        "error": {
+            "root_cause": [{"type": "illegal_argument_exception", "reason": reason}],
+            "type": "illegal_argument_exception",
+            "reason": reason,
+        },
+        "status": 400,
+    });
+    Response::builder()
+        .status(400)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Response rejecting a request without credentials, or `given` wrong ones (401),
+/// in the format of the OpenSearch security plugin
+fn unauthenticated_response(path: &str, given: bool) -> Response<ProxyBody> {
+    let reason = if given {
+        format!("unable to authenticate user for REST request [{}]", path)
+    } else {
+        format!(
+            "missing authentication credentials for REST request [{}]",
+            path
+        )
+    };
+    let body = json!({
+        "error": {
+            "root_cause": [{"type": "security_exception", "reason": reason}],
+            "type": "security_exception",
+            "reason": reason,
+        },
+        "status": 401,
+    });
+    Response::builder()
+        .status(401)
+        .header("WWW-Authenticate", "Basic realm=\"OpenSearch Security\"")
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Response rejecting a body which isn't valid JSON (400), in the OpenSearch format
+fn malformed_body_response(err: &serde_json::Error) -> Response<ProxyBody> {
+    let reason = format!("Failed to parse request body: {}", err);
+    let body = json!({
+        "error": {
+            "root_cause": [{"type": "parsing_exception", "reason": reason}],
+            "type": "parsing_exception",
+            "reason": reason,
+            "line": err.line(),
+            "col": err.column(),
+        },
+        "status": 400,
+    });
+    Response::builder()
+        .status(400)
+        .header("Content-Type", "application/json; charset=UTF-8")
+        .body(full_body(Bytes::from(body.to_string())))
+        .unwrap()
+}
+
+/// Connector for the upstream client, speaking TLS when `--upstream-tls` is set
+fn upstream_connector(config: &Config) -> Result<HttpsConnector<HttpConnector>, String> {
+    let mut http = HttpConnector::new();
+    // The https:// URLs are handled by the TLS connector wrapping this one
+    http.enforce_http(false);
+    let tls_config = if config.upstream_tls {
+        tls::client_config(
+            config.upstream_ca_cert.as_deref(),
+            config.upstream_tls_insecure,
+        )?
+    } else {
+        // Unused, the URLs are all http://
+        rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth()
+    };
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http();
+    let builder = match &config.upstream_tls_server_name {
+        Some(server_name) => builder.with_server_name_resolver(FixedServerNameResolver::new(
+            ServerName::try_from(server_name.clone())
+                .map_err(|err| format!("invalid TLS server name {}: {}", server_name, err))?,
+        )),
+        None => builder,
+    };
+    Ok(builder.enable_http1().wrap_connector(http))
+}
+
+/// Print once at startup what will be emulated and what forwarded to OpenSearch
+fn print_startup_banner(config: &Config) {
+    println!("emulating endpoints: /{{index}}/_search, /_search, /{{index}}/_count, /_msearch, /_cat/indices, GET /{{index}}/_doc/{{id}}");
+    println!("emulating features: {}", SUPPORTED_FEATURES.join(", "));
+    if config.standalone {
+        println!("standalone: nothing is forwarded, unsupported queries are answered with a 501");
+    } else {
+        println!("forwarding: all other endpoints and unsupported queries");
+    }
+    if !config.path_rewrite.is_empty() {
+        let rules: Vec<_> = config
+            .path_rewrite
+            .iter()
+            .map(|rule| format!("{}={}", rule.from, rule.to))
+            .collect();
+        println!("rewriting forwarded paths: {}", rules.join(", "));
+    }
+    match config.forward_policy() {
+        _ if config.standalone => {}
+        ForwardPolicy::All => {}
+        ForwardPolicy::None => println!("forward policy: nothing is forwarded"),
+        ForwardPolicy::Allowlist => {
+            let allowlist: Vec<_> = config
+                .forward_allow
+                .iter()
+                .map(|allowed| allowed.as_str())
+                .collect();
+            println!("forward policy: only {}", allowlist.join(", "));
+        }
+    }
+    if config.inject_shard_failures > 0.0 {
+        println!(
+            "injecting shard failures: {}% of emulated responses",
+            config.inject_shard_failures * 100.0
+        );
+    }
+    if config.chaos_latency_ms > 0 || config.chaos_error_rate > 0.0 {
+        println!(
+            "chaos mode: {}ms added latency, {}% of emulated searches failing",
+            config.chaos_latency_ms,
+            config.chaos_error_rate * 100.0
+        );
+    }
+    if config.emulate_cluster_health {
+        println!("emulating /_cluster/health: always green");
+    }
+    if config.emulate_root_info {
+        println!("emulating GET /: OpenSearch {}", config.root_info_version);
+    }
+    if let Some(auth) = &config.upstream_auth {
+        let scheme = auth
+            .to_str()
+            .ok()
+            .and_then(|auth| auth.split(' ').next())
+            .unwrap_or("");
+        println!(
+            "adding {} Authorization to forwarded requests, required on emulated endpoints",
+            scheme
+        );
+    }
+    if config.sample_passthrough > 0.0 {
+        println!(
+            "sampling forwarded requests: {}%",
+            config.sample_passthrough * 100.0
+        );
+    }
+}
+
+/// Add the operations of a forwarded _bulk request to the stats, unless its body is malformed
+/// or decompresses past `max_bytes`: counting isn't worth inflating it
+fn count_bulk_request(req: &Request<Bytes>, max_bytes: usize, stats: &Stats) {
+    let Some(actions) = decode_request_body(req.headers(), req.body(), max_bytes)
+        .ok()
+        .and_then(|body| count_bulk_actions(&body))
+    else {
+        debug!("not counting the operations of a malformed _bulk body");
+        return;
+    };
+    for (counter, count) in [
+        (&stats.bulk_index_count, actions.index),
+        (&stats.bulk_create_count, actions.create),
+        (&stats.bulk_update_count, actions.update),
+        (&stats.bulk_delete_count, actions.delete),
+    ] {
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM (container stop), then wake up the accept loop
+async fn notify_on_shutdown_signal(shutdown: Arc<Notify>) {
+    let mut sigterm = signal(SignalKind::terminate()).expect("can't listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+    // Keeps a permit, so the accept loop stops even if it isn't waiting right now
+    shutdown.notify_one();
+}
+
+/// Check that OpenSearch accepts connections at every interval, for the health check.
+/// It's reachable as long as one of its nodes is.
+async fn probe_backend_periodically(
+    out_addrs: Vec<SocketAddr>,
+    interval: Duration,
+    stats: Arc<Stats>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    // A slow probe delays the next one, instead of piling them up
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let mut reachable = false;
+        for out_addr in &out_addrs {
+            if probe_backend(out_addr).await.is_ok() {
+                reachable = true;
+                break;
+            }
+        }
+        stats.upstream_reachable.store(reachable, Ordering::Relaxed);
+    }
+}
+
+/// Try to connect once to OpenSearch, to surface misconfiguration at startup
+async fn probe_backend(out_addr: &SocketAddr) -> Result<(), String> {
+    match tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(out_addr)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("connection timed out".to_string()),
+    }
+}
+
+/// Escape text sent by the clients (tenants, URIs, bodies) for the monitoring pages,
+/// which insert the HTML fragments as they are
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn get_queries_failures(stats: Arc<Stats>) -> String {
+    let failures = stats.search_queries_failures.lock().unwrap();
+    let mut result = "".to_owned();
+
+    for (reason, body) in failures.iter() {
+        result.push_str(format!("<div class='failure_row'><div class='failure_reason'>{}</div> <div class='failure_body'>{}</div></div>", escape_html(reason), escape_html(&format!("{:#?}", std::str::from_utf8(body).unwrap_or("")))).as_str());
+    }
+    result
+}
+
+fn get_passthrough_samples(stats: Arc<Stats>) -> String {
+    let samples = stats.passthrough_samples.lock().unwrap();
+    let mut result = "".to_owned();
+
+    for (reason, body) in samples.iter().rev() {
+        result.push_str(format!("<div class='failure_row'><div class='failure_reason'>{}</div> <div class='failure_body'>{}</div></div>", escape_html(reason), escape_html(&format!("{:#?}", std::str::from_utf8(body).unwrap_or("")))).as_str());
+    }
+    result
+}
+
+fn get_tenants(stats: Arc<Stats>) -> String {
+    let tenants = stats.tenants.lock().unwrap();
+    let mut tenants: Vec<_> = tenants.iter().collect();
+    tenants.sort_by_key(|(tenant, _)| *tenant);
+
+    let mut result = "".to_owned();
+    for (tenant, tenant_stats) in tenants {
+        result.push_str(
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(tenant),
+                tenant_stats.search_queries_success_count,
+                tenant_stats.search_queries_failure_count,
+                tenant_stats.nonsearch_passed_through_count
+            )
+            .as_str(),
+        );
+    }
+    result
+}
+
+/// Counters and latencies in the Prometheus text format
+fn get_metrics(stats: Arc<Stats>) -> String {
+    let mut result = "".to_owned();
+    for (name, counter) in stats.counters() {
+        result.push_str(&format!(
+            "# TYPE tinyq_{name} counter\ntinyq_{name} {}\n",
+            counter.load(Ordering::Relaxed)
+        ));
+    }
+    for (name, latencies) in [
+        ("search_latency_seconds", &stats.search_latency),
+        ("forward_latency_seconds", &stats.forward_latency),
+    ] {
+        let histogram = latencies.0.lock().unwrap();
+        result.push_str(&format!("# TYPE tinyq_{name} summary\n"));
+        for percentile in LATENCY_PERCENTILES {
+            result.push_str(&format!(
+                "tinyq_{name}{{quantile=\"{}\"}} {}\n",
+                percentile / 100.0,
+                histogram.value_at_percentile(percentile) as f64 / 1_000_000.0
+            ));
+        }
+        // The histogram doesn't keep the exact sum
+        result.push_str(&format!(
+            "tinyq_{name}_sum {}\ntinyq_{name}_count {}\n",
+            histogram.mean() * histogram.len() as f64 / 1_000_000.0,
+            histogram.len()
+        ));
+    }
+    result
+}
+
+fn get_search_cache(stats: Arc<Stats>) -> String {
+    format!(
+        "{} hits, {} misses",
+        stats.search_cache_hits_count.load(Ordering::Relaxed),
+        stats.search_cache_misses_count.load(Ordering::Relaxed)
+    )
+}
+
+fn get_bulk_operations(stats: Arc<Stats>) -> String {
+    format!(
+        "{} index, {} create, {} update, {} delete",
+        stats.bulk_index_count.load(Ordering::Relaxed),
+        stats.bulk_create_count.load(Ordering::Relaxed),
+        stats.bulk_update_count.load(Ordering::Relaxed),
+        stats.bulk_delete_count.load(Ordering::Relaxed)
+    )
+}
+
+use axum::http::header::CONTENT_TYPE;
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use tower_http::compression::CompressionLayer;
+use tower_http::services::ServeFile;
+
+/// Monitoring website built into the binary, so it runs from any working directory
+const INDEX_HTML: &str = include_str!("../../frontend/index.html");
+const FAVICON: &[u8] = include_bytes!("../../frontend/favicon.ico");
+
+/// Everything serving the requests of the proxy port, shared by the connections
+#[derive(Clone)]
+struct Proxy {
+    client: Arc<UpstreamClient>,
+    stats: Arc<Stats>,
+    store: Arc<CurrentStore>,
+    config: Arc<Config>,
+    chaos: Arc<Chaos>,
+    access_log: Option<Arc<AccessLog>>,
+    cache: Arc<SearchCache>,
+}
+
+impl Proxy {
+    /// Answer a request of a client: collect its body, handle it, then add the
+    /// headers of the emulated responses and log it
+    async fn serve(self, req: Request<Incoming>) -> Result<Response<ProxyBody>, hyper::Error> {
+        let Proxy {
+            client,
+            stats,
+            store,
+            config,
+            chaos,
+            access_log,
+            cache,
+        } = self;
+        let (received_at, start) = (SystemTime::now(), Instant::now());
+        // Kept for the access log, the request is consumed
+        let access_log_request = access_log.as_ref().map(|_| {
+            let x_opaque_id = req
+                .headers()
+                .get("x-opaque-id")
+                .and_then(|id| id.to_str().ok())
+                .map(str::to_string);
+            (
+                req.method().clone(),
+                req.uri().path().to_string(),
+                x_opaque_id,
+            )
+        });
+        let cors_origin = config.cors_origin.clone();
+        let response_config = config.clone();
+        let max_body_bytes = config.max_body_bytes;
+        let mut res = match request_with_streamed_body(req, max_body_bytes).await {
+            Ok(mut req) => {
+                redact_credentials(req.headers_mut());
+                debug!(headers = ?req.headers(), "received request");
+                trace!(body = ?req.body(), "received request body");
+                handle_request(&client, req, stats, store, config, chaos, cache).await
+            }
+            Err(BodyError::TooLarge) => decided(
+                body_too_large_response(max_body_bytes),
+                &Decision::BodyTooLarge,
+                false,
+            ),
+            Err(BodyError::Incoming(err)) => return Err(err),
+        };
+        // Emulated or forwarded, unless OpenSearch has its own CORS settings
+        if let Some(cors_origin) = cors_origin {
+            res.headers_mut()
+                .entry(ACCESS_CONTROL_ALLOW_ORIGIN)
+                .or_insert(cors_origin);
+        }
+        // OpenSearch responses are passed as they are
+        let forwarded = res
+            .extensions()
+            .get::<Outcome>()
+            .is_none_or(|outcome| outcome.0 == "forwarded");
+        if !forwarded {
+            add_response_headers(
+                res.headers_mut(),
+                &response_config.response_header,
+                response_config.override_response_headers,
+            );
+        }
+        info!(status = res.status().as_u16(), "responded");
+        debug!(headers = ?res.headers(), "response");
+        if let (Some(access_log), Some((method, path, x_opaque_id))) =
+            (access_log, access_log_request)
+        {
+            access_log.log(&AccessLogEntry {
+                timestamp: rfc3339(received_at),
+                method: method.as_str(),
+                path: &path,
+                index: path_index(&path),
+                outcome: res
+                    .extensions()
+                    .get::<Outcome>()
+                    .map_or("forwarded", |o| o.0),
+                status: res.status().as_u16(),
+                duration_ms: start.elapsed().as_micros() as f64 / 1000.0,
+                bytes_out: hyper::body::Body::size_hint(res.body()).exact(),
+                x_opaque_id: x_opaque_id.as_deref(),
+            });
+        }
+
+        Ok(res)
+    }
+}
+
+/// Routes of the monitoring website: the stats, the logs and the configuration
+fn monitoring_app(stats: Arc<Stats>, config: Arc<Config>) -> Result<Router, String> {
+    let stats1 = stats;
+    let stats2 = stats1.clone();
+    let stats3 = stats1.clone();
+    let stats4 = stats1.clone();
+    let stats6 = stats1.clone();
+    let stats7 = stats1.clone();
+    let stats8 = stats1.clone();
+    let stats9 = stats1.clone();
+    let stats10 = stats1.clone();
+    let stats11 = stats1.clone();
+    let stats12 = stats1.clone();
+    let stats13 = stats1.clone();
+    let stats14 = stats1.clone();
+    let stats15 = stats1.clone();
+    let stats16 = stats1.clone();
+    let stats17 = stats1.clone();
+    let stats18 = stats1.clone();
+    let stats19 = stats1.clone();
+    let stats20 = stats1.clone();
+    let stats21 = stats1.clone();
+    let stats22 = stats1.clone();
+    let stats23 = stats1.clone();
+    let stats24 = stats1.clone();
+    let monitor_config = config.clone();
+
+    let mut app = Router::new()
+        .route(
+            "/search_queries_success_count",
+            get(move || async move {
+                format!(
+                    "{}",
+                    stats1.search_queries_success_count.load(Ordering::Relaxed)
+                )
+            }),
+        )
+        .route(
+            "/search_queries_failure_count",
+            get(move || async move {
+                format!(
+                    "{}",
+                    stats2.search_queries_failure_count.load(Ordering::Relaxed)
+                )
+            }),
+        )
+        .route(
+            "/count_queries_success_count",
+            get(move || async move {
+                format!(
+                    "{}",
+                    stats11.count_queries_success_count.load(Ordering::Relaxed)
+                )
+            }),
+        )
+        .route(
+            "/search_queries_failures",
+            get(move || async move { get_queries_failures(stats3) }),
+        )
+        .route(
+            "/failures_dropped_count",
+            get(move || async move {
+                format!("{}", stats10.failures_dropped_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/nonsearch_passed_through_count",
+            get(move || async move {
+                format!(
+                    "{}",
+                    stats4
+                        .nonsearch_passed_through_count
+                        .load(Ordering::Relaxed)
+                )
+            }),
+        )
+        .route(
+            "/scroll_requests_count",
+            get(move || async move {
+                format!("{}", stats22.scroll_requests_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/injected_shard_failures_count",
+            get(move || async move {
+                format!(
+                    "{}",
+                    stats8.injected_shard_failures_count.load(Ordering::Relaxed)
+                )
+            }),
+        )
+        .route(
+            "/upstream_errors_count",
+            get(move || async move {
+                format!("{}", stats9.upstream_errors_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/upstream_retries_count",
+            get(move || async move {
+                format!("{}", stats23.upstream_retries_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/breaker_rejected_count",
+            get(move || async move {
+                format!("{}", stats24.breaker_rejected_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/stats.json",
+            get(move || async move { Json(serde_json::to_value(&*stats12).unwrap()) }),
+        )
+        .route(
+            "/blocked_requests_count",
+            get(move || async move {
+                format!("{}", stats14.blocked_requests_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/chaos_errors_count",
+            get(move || async move {
+                format!("{}", stats16.chaos_errors_count.load(Ordering::Relaxed))
+            }),
+        )
+        .route(
+            "/search_latency_p50",
+            get(
+                move || async move { format!("{:.3}", stats17.search_latency.percentile_ms(50.0)) },
+            ),
+        )
+        .route(
+            "/search_latency_p95",
+            get(
+                move || async move { format!("{:.3}", stats18.search_latency.percentile_ms(95.0)) },
+            ),
+        )
+        .route(
+            "/search_latency_p99",
+            get(
+                move || async move { format!("{:.3}", stats19.search_latency.percentile_ms(99.0)) },
+            ),
+        )
+        .route(
+            "/search_cache",
+            get(move || async move { get_search_cache(stats21) }),
+        )
+        .route("/metrics", get(move || async move { get_metrics(stats20) }))
+        .route(
+            "/config",
+            get(move || async move { Json(serde_json::to_value(&*monitor_config).unwrap()) }),
+        )
+        .route(
+            "/bulk_operations",
+            get(move || async move { get_bulk_operations(stats15) }),
+        )
+        .route("/tenants", get(move || async move { get_tenants(stats6) }))
+        .route(
+            "/passthrough_samples",
+            get(move || async move { get_passthrough_samples(stats7) }),
+        );
+    app = match &config.frontend_dir {
+        Some(dir) => {
+            let (index_html, favicon) = (dir.join("index.html"), dir.join("favicon.ico"));
+            // Checked up front, ServeFile would only answer 404s
+            for file in [&index_html, &favicon] {
+                if !file.is_file() {
+                    return Err(format!("missing frontend file {}", file.display()));
+                }
+            }
+            app.route_service("/", ServeFile::new(index_html))
+                .route_service("/favicon.ico", ServeFile::new(favicon))
+        }
+        None => app.route("/", get(|| async { Html(INDEX_HTML) })).route(
+            "/favicon.ico",
+            get(|| async { ([(CONTENT_TYPE, "image/x-icon")], FAVICON) }),
+        ),
+    };
+    if config.allow_reset {
+        app = app.route(
+            "/reset",
+            post(move || async move {
+                stats13.reset();
+                "stats reset\n"
+            }),
+        );
+    }
+
+    // The failures dump can get big, gzipped for the clients accepting it
+    Ok(app.layer(CompressionLayer::new()))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Arc::new(Config::parse());
+
+    // Request logs are filtered with RUST_LOG, e.g. RUST_LOG=proxy=debug for the headers,
+    // RUST_LOG=proxy=trace for the bodies too
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    // Loaded before anything starts listening, a broken documents file is fatal
+    let store = match &config.documents {
+        Some(path) => DocumentStore::load(path)?,
+        None => DocumentStore::default(),
+    };
+    println!("Serving {} documents", store.documents().len());
+    let store = Arc::new(CurrentStore::new(store));
+    let access_log = match &config.access_log {
+        Some(path) => Some(Arc::new(AccessLog::open(path)?)),
+        None => None,
+    };
+
+    // Monitoring website
+    let stats = Arc::new(Stats::default());
+    let app = monitoring_app(stats.clone(), config.clone())?;
+
+    let addr = config.monitor_listen;
+    println!("listening on {}", addr);
+    let monitor_handle = hyper_server::Handle::new();
+    let monitor_server = hyper_server::bind(addr).handle(monitor_handle.clone());
+    let monitor = tokio::task::spawn(async move {
+        monitor_server.serve(app.into_make_service()).await.unwrap();
+    });
+
+    // Proxy
+    let in_addr = config.listen;
+    let client = Arc::new(UpstreamClient::new(&config)?);
+
+    let listener = TcpListener::bind(in_addr).await?;
+
+    println!("Listening on http://{}", in_addr);
+    println!("Proxying to {}", client.upstreams.urls().join(", "));
+    print_startup_banner(&config);
+
+    // With several nodes, startup is only aborted when none of them is reachable
+    if config.startup_probe_backend {
+        let mut unreachable = Vec::new();
+        for out_addr in &config.upstream {
+            match probe_backend(out_addr).await {
+                Ok(()) => println!("OpenSearch at {} is reachable", out_addr),
+                Err(err) => {
+                    println!(
+                        "Warning: OpenSearch at {} is unreachable: {}",
+                        out_addr, err
+                    );
+                    unreachable.push(format!("{}: {}", out_addr, err));
+                }
+            }
+        }
+        if config.require_backend && unreachable.len() == config.upstream.len() {
+            return Err(format!("OpenSearch is unreachable: {}", unreachable.join(", ")).into());
+        }
+    }
+
+    // No OpenSearch in standalone mode, the health check reports it unreachable
+    if !config.standalone {
+        tokio::task::spawn(probe_backend_periodically(
+            config.upstream.clone(),
+            Duration::from_millis(config.health_probe_interval_ms),
+            stats.clone(),
+        ));
+    }
+
+    let chaos = Arc::new(Chaos::new(&config));
+    let cache = Arc::new(SearchCache::new(config.search_cache_size));
+    let proxy = Proxy {
+        client,
+        stats: stats.clone(),
+        store: store.clone(),
+        config: config.clone(),
+        chaos,
+        access_log,
+        cache,
+    };
+    let shutdown = Arc::new(Notify::new());
+    tokio::task::spawn(notify_on_shutdown_signal(shutdown.clone()));
+    // Tracks the connections, to let their in-flight requests finish on shutdown
+    let graceful = GracefulShutdown::new();
+    // HTTP/1.1, or HTTP/2 for clients starting with its preface (h2c with prior knowledge).
+    // Each HTTP/2 stream is a request of its own going through the service below, so the
+    // stats count the multiplexed requests one by one, like the requests of a keep-alive
+    // HTTP/1.1 connection.
+    let server = auto::Builder::new(TokioExecutor::new());
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            () = shutdown.notified() => break,
+        };
+        let io = TokioIo::new(stream);
+
+        let proxy = proxy.clone();
+        let service = service_fn(move |req: hyper::Request<Incoming>| {
+            let span = info_span!("request", method = %req.method(), path = req.uri().path());
+            proxy.clone().serve(req).instrument(span)
+        });
+
+        let connection = graceful.watch(server.serve_connection(io, service).into_owned());
+        tokio::task::spawn(async move {
+            if let Err(err) = connection.await {
+                warn!("failed to serve the connection: {:?}", err);
+            }
+        });
+    }
+
+    // Stop accepting connections, idle keep-alive ones are closed right away
+    drop(listener);
+    println!(
+        "Shutting down, waiting up to {:?} for in-flight requests",
+        config.shutdown_grace()
+    );
+    if tokio::time::timeout(config.shutdown_grace(), graceful.shutdown())
+        .await
+        .is_err()
+    {
+        println!("Grace period elapsed, dropping the remaining requests");
+    }
+    monitor_handle.shutdown();
+    monitor.await?;
+
+    println!("Final stats: {}", serde_json::to_string(&*stats)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const OK: &str = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}";
+
+    fn test_config(args: &[&str]) -> Config {
+        Config::parse_from(std::iter::once("proxy").chain(args.iter().copied()))
+    }
+
+    /// Mock OpenSearch node answering its connections in turn with the responses,
+    /// None closing the connection without answering
+    async fn mock_upstream(responses: Vec<Option<&'static str>>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                read_request_head(&mut stream).await;
+                if let Some(response) = response {
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /// Read the request until the end of its headers, with what came of the body along
+    async fn read_request_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|end| end == b"\r\n\r\n") {
+            let read = stream.read(&mut buf).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8_lossy(&head).into_owned()
+    }
+
+    fn get(path: &str) -> Request<Bytes> {
+        Request::get(path).body(Bytes::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwarded_read_is_retried_until_opensearch_answers() {
+        let upstream = mock_upstream(vec![None, Some(OK)]).await.to_string();
+        let config = test_config(&["--upstream", &upstream, "--upstream-retry-backoff-ms", "1"]);
+        let client = UpstreamClient::new(&config).unwrap();
+        let retries = AtomicU64::new(0);
+        let res = forward_request_to_opensearch(
+            &client,
+            &get("/_cat/indices"),
+            config.upstream_timeout(),
+            config.upstream_retry(),
+            &retries,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(retries.load(Ordering::Relaxed), 1);
+    }
+
+    /// Body of the response of the forwarded request
+    async fn forward(client: &UpstreamClient, config: &Config) -> String {
+        let res = forward_request_to_opensearch(
+            client,
+            &get("/_cat/indices"),
+            config.upstream_timeout(),
+            config.upstream_retry(),
+            &AtomicU64::new(0),
+        )
+        .await
+        .unwrap();
+        String::from_utf8(body_bytes(res).await.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forwarded_requests_are_spread_over_the_nodes() {
+        const NODE_A: &str = "HTTP/1.1 200 OK\r\ncontent-length: 1\r\nconnection: close\r\n\r\na";
+        const NODE_B: &str = "HTTP/1.1 200 OK\r\ncontent-length: 1\r\nconnection: close\r\n\r\nb";
+        let node_a = mock_upstream(vec![Some(NODE_A); 2]).await;
+        let node_b = mock_upstream(vec![Some(NODE_B); 2]).await;
+        let config = test_config(&["--upstream", &format!("{},{}", node_a, node_b)]);
+        let client = UpstreamClient::new(&config).unwrap();
+        let mut bodies = Vec::new();
+        for _ in 0..4 {
+            bodies.push(forward(&client, &config).await);
+        }
+        assert_eq!(bodies, ["a", "b", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn node_refusing_connections_is_skipped() {
+        let node_a = mock_upstream(vec![Some(OK); 2]).await;
+        let node_b = closed_port().await;
+        let config = test_config(&[
+            "--upstream",
+            &format!("{},{}", node_a, node_b),
+            "--upstream-retry-backoff-ms",
+            "1",
+        ]);
+        let client = UpstreamClient::new(&config).unwrap();
+        assert_eq!(forward(&client, &config).await, "{}");
+        // Refused by the second node, then retried on the first one
+        assert_eq!(forward(&client, &config).await, "{}");
+        // The second node is skipped, until its turn comes after `--upstream-skip-ms`
+        assert_eq!(client.upstreams.pick().addr, node_a);
+        assert_eq!(client.upstreams.pick().addr, node_a);
+    }
+
+    #[tokio::test]
+    async fn trailers_of_forwarded_responses_are_kept() {
+        const CHUNKED_WITH_TRAILER: &str = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\
+            trailer: x-checksum\r\nconnection: close\r\n\r\n\
+            2\r\n{}\r\n0\r\nx-checksum: abc\r\n\r\n";
+        let upstream = mock_upstream(vec![Some(CHUNKED_WITH_TRAILER)])
+            .await
+            .to_string();
+        let config = test_config(&["--upstream", &upstream]);
+        let client = UpstreamClient::new(&config).unwrap();
+        let res = forward_request_to_opensearch(
+            &client,
+            &get("/_cat/indices"),
+            config.upstream_timeout(),
+            config.upstream_retry(),
+            &AtomicU64::new(0),
+        )
+        .await
+        .unwrap();
+        let body = res.into_body().collect().await.unwrap();
+        assert_eq!(body.trailers().unwrap()["x-checksum"], "abc");
+        assert_eq!(body.to_bytes(), "{}");
+    }
+
+    #[tokio::test]
+    async fn forwarded_write_is_not_retried() {
+        let upstream = mock_upstream(vec![None, Some(OK)]).await.to_string();
+        let config = test_config(&["--upstream", &upstream, "--upstream-retry-backoff-ms", "1"]);
+        let client = UpstreamClient::new(&config).unwrap();
+        let retries = AtomicU64::new(0);
+        let req = Request::post("/_bulk").body(Bytes::new()).unwrap();
+        let res = forward_request_to_opensearch(
+            &client,
+            &req,
+            config.upstream_timeout(),
+            config.upstream_retry(),
+            &retries,
+        )
+        .await;
+        assert!(matches!(res, Err(ForwardError::Upstream(_))));
+        assert_eq!(retries.load(Ordering::Relaxed), 0);
+    }
+
+    /// Proxy with the configuration, handling the requests one by one
+    struct TestProxy {
+        client: Arc<UpstreamClient>,
+        stats: Arc<Stats>,
+        store: Arc<CurrentStore>,
+        config: Arc<Config>,
+        chaos: Arc<Chaos>,
+        cache: Arc<SearchCache>,
+    }
+
+    impl TestProxy {
+        /// Proxy serving the lyrics
+        fn new(args: &[&str]) -> Self {
+            TestProxy::with_store(args, DocumentStore::default())
+        }
+
+        fn with_store(args: &[&str], store: DocumentStore) -> Self {
+            let config = test_config(args);
+            TestProxy {
+                client: Arc::new(UpstreamClient::new(&config).unwrap()),
+                stats: Arc::new(Stats::default()),
+                store: Arc::new(CurrentStore::new(store)),
+                chaos: Arc::new(Chaos::new(&config)),
+                cache: Arc::new(SearchCache::new(config.search_cache_size)),
+                config: Arc::new(config),
+            }
+        }
+
+        async fn handle(&self, req: Request<Bytes>) -> Response<ProxyBody> {
+            handle_request(
+                &self.client,
+                req,
+                self.stats.clone(),
+                self.store.clone(),
+                self.config.clone(),
+                self.chaos.clone(),
+                self.cache.clone(),
+            )
+            .await
+        }
+
+        /// Serve the proxy port on an ephemeral port, for the requests to go through hyper
+        async fn listen(&self) -> SocketAddr {
+            let proxy = Proxy {
+                client: self.client.clone(),
+                stats: self.stats.clone(),
+                store: self.store.clone(),
+                config: self.config.clone(),
+                chaos: self.chaos.clone(),
+                access_log: None,
+                cache: self.cache.clone(),
+            };
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::task::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let proxy = proxy.clone();
+                    let service = service_fn(move |req| proxy.clone().serve(req));
+                    tokio::task::spawn(
+                        auto::Builder::new(TokioExecutor::new())
+                            .serve_connection(TokioIo::new(stream), service)
+                            .into_owned(),
+                    );
+                }
+            });
+            addr
+        }
+
+        async fn post(&self, path: &str, body: Value) -> Response<ProxyBody> {
+            let req = Request::post(path)
+                .header("content-type", "application/json")
+                .body(Bytes::from(body.to_string()))
+                .unwrap();
+            self.handle(req).await
+        }
+    }
+
+    async fn body_bytes(res: Response<ProxyBody>) -> Bytes {
+        res.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    async fn body_json(res: Response<ProxyBody>) -> Value {
+        serde_json::from_slice(&body_bytes(res).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn equivalent_search_is_served_from_the_cache() {
+        let proxy = TestProxy::new(&["--standalone"]);
+        let query = json!({"multi_match": {"query": "fire"}});
+        let first = proxy
+            .post(
+                "/_search?typed_keys=false&lenient=false",
+                json!({"query": query}),
+            )
+            .await;
+        assert_eq!(first.status(), StatusCode::OK);
+        // Options in another order, defaults spelled out
+        let second = proxy
+            .post(
+                "/_search?lenient=false&typed_keys=false",
+                json!({"size": 10, "from": 0, "query": {"bool": {"must": [query]}}}),
+            )
+            .await;
+        let hits = proxy.stats.search_cache_hits_count.load(Ordering::Relaxed);
+        let misses = proxy
+            .stats
+            .search_cache_misses_count
+            .load(Ordering::Relaxed);
+        assert_eq!((hits, misses), (1, 1));
+        assert_eq!(body_json(second).await, body_json(first).await);
+        // Not the same search, nor the same endpoint
+        proxy.post("/_count", json!({"query": query})).await;
+        let hits = proxy.stats.search_cache_hits_count.load(Ordering::Relaxed);
+        assert_eq!(hits, 1);
+    }
+
+    /// Address nothing listens on, connections to it are refused
+    async fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn breaker_opens_while_opensearch_is_down() {
+        let upstream = mock_upstream(vec![None, Some(OK)]).await.to_string();
+        let proxy = TestProxy::new(&[
+            "--upstream",
+            &upstream,
+            "--upstream-retries",
+            "0",
+            "--breaker-threshold",
+            "1",
+            "--breaker-cooldown-ms",
+            "100",
+        ]);
+        let breaker = || serde_json::to_value(&*proxy.stats).unwrap()["upstream_breaker"].clone();
+        let res = proxy.handle(get("/_cluster/settings")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let res = proxy.handle(get("/_cluster/settings")).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let rejected = proxy.stats.breaker_rejected_count.load(Ordering::Relaxed);
+        assert_eq!(rejected, 1);
+        assert_eq!(breaker(), "open");
+        // Once OpenSearch is back, the trial request after the cooldown closes it
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let res = proxy.handle(get("/_cluster/settings")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(breaker(), "closed");
+    }
+
+    #[tokio::test]
+    async fn client_text_is_escaped_on_the_monitoring_pages() {
+        let upstream = closed_port().await;
+        let proxy = TestProxy::new(&["--upstream", &upstream, "--sample-passthrough", "1"]);
+        let req = Request::post("/_cluster/settings")
+            .header("x-tenant-id", "<img src=x onerror=alert(1)>")
+            .body(Bytes::from("<script>alert(1)</script>"))
+            .unwrap();
+        proxy.handle(req).await;
+        let tenants = get_tenants(proxy.stats.clone());
+        assert!(
+            tenants.contains("&lt;img src=x onerror=alert(1)&gt;"),
+            "{}",
+            tenants
+        );
+        let samples = get_passthrough_samples(proxy.stats.clone());
+        assert!(samples.contains("&lt;script&gt;alert(1)"), "{}", samples);
+    }
+
+    #[tokio::test]
+    async fn unreachable_opensearch_gives_bad_gateway() {
+        let upstream = closed_port().await;
+        let proxy = TestProxy::new(&["--upstream", &upstream, "--upstream-retries", "0"]);
+        let res = proxy.handle(get("/_cluster/settings")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let body = body_json(res).await;
+        assert_eq!(body["error"]["type"], "upstream_unavailable");
+        assert_eq!(body["status"], 502);
+        let errors = proxy.stats.upstream_errors_count.load(Ordering::Relaxed);
+        assert_eq!(errors, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn counters_are_not_lost_under_concurrency() {
+        let stats = Arc::new(Stats::default());
+        let tasks: Vec<_> = (0..64)
+            .map(|_| {
+                let stats = stats.clone();
+                tokio::task::spawn(async move {
+                    for _ in 0..1000 {
+                        stats
+                            .search_queries_success_count
+                            .fetch_add(1, Ordering::Relaxed);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let count = stats.search_queries_success_count.load(Ordering::Relaxed);
+        assert_eq!(count, 64_000);
+    }
+
+    #[tokio::test]
+    async fn hung_opensearch_gives_gateway_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        // Accepts the connection and reads the request, but never answers
+        tokio::task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request_head(&mut stream).await;
+            std::future::pending::<()>().await;
+        });
+        let proxy = TestProxy::new(&[
+            "--upstream",
+            &upstream,
+            "--upstream-timeout-ms",
+            "50",
+            "--upstream-retries",
+            "0",
+        ]);
+        let res = proxy.handle(get("/_cluster/settings")).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body_json(res).await["error"]["type"], "upstream_timeout");
+    }
+
+    #[tokio::test]
+    async fn gzipped_search_round_trips() {
+        let proxy = TestProxy::new(&["--standalone"]);
+        let body = json!({"query": {"multi_match": {"query": "fire"}}}).to_string();
+        let gzipped = encoding::encode(encoding::Encoding::Gzip, body.as_bytes());
+        let req = Request::post("/_search")
+            .header("content-type", "application/json")
+            .header("content-encoding", "gzip")
+            .header("accept-encoding", "gzip")
+            .body(Bytes::from(gzipped))
+            .unwrap();
+        let res = proxy.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+        let body = body_bytes(res).await;
+        let response: Value =
+            serde_json::from_reader(flate2::read::GzDecoder::new(&*body)).unwrap();
+        assert_eq!(response["hits"]["total"]["value"], 1);
+    }
+
+    #[test]
+    fn stats_json_has_all_the_stats() {
+        let stats = Stats::default();
+        stats.search_queries_failures.lock().unwrap().push_back((
+            "unimplemented query".to_string(),
+            Bytes::from_static(b"{\"query\": {\"fuzzy\": {}}}"),
+        ));
+        let json = serde_json::to_value(&stats).unwrap();
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        for (name, _) in stats.counters() {
+            assert!(keys.contains(&name), "missing {} in {:?}", name, keys);
+        }
+        for name in [
+            "upstream_reachable",
+            "upstream_breaker",
+            "search_latency",
+            "forward_latency",
+            "search_queries_failures_count",
+            "passthrough_samples_count",
+            "tenants",
+        ] {
+            assert!(keys.contains(&name), "missing {} in {:?}", name, keys);
+        }
+        assert_eq!(keys.len(), stats.counters().len() + 7);
+        // The count of the failures only, not their bodies
+        assert_eq!(json["search_queries_failures_count"], 1);
+        assert!(!json.to_string().contains("fuzzy"));
+    }
+
+    #[tokio::test]
+    async fn reset_zeroes_the_stats() {
+        let proxy = TestProxy::new(&["--standalone"]);
+        proxy
+            .post("/_search", json!({"query": {"match_all": {}}}))
+            .await;
+        proxy
+            .post("/_search", json!({"query": {"fuzzy": {}}}))
+            .await;
+        let stats = &proxy.stats;
+        assert_eq!(
+            stats.search_queries_success_count.load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(
+            stats.search_queries_failure_count.load(Ordering::Relaxed),
+            1
+        );
+        stats.reset();
+        for (name, counter) in stats.counters() {
+            assert_eq!(counter.load(Ordering::Relaxed), 0, "{}", name);
+        }
+        assert!(stats.search_queries_failures.lock().unwrap().is_empty());
+        assert_eq!(stats.search_latency.percentile_ms(50.0), 0.0);
+        // Resetting again changes nothing
+        stats.reset();
+        assert_eq!(
+            stats.search_queries_success_count.load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_search_is_rejected_without_forwarding() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        let proxy = TestProxy::new(&["--upstream", &upstream]);
+        let req = Request::post("/_search")
+            .header("content-type", "application/json")
+            .body(Bytes::from("{not json"))
+            .unwrap();
+        let res = proxy.handle(req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(res).await;
+        assert_eq!(body["error"]["type"], "parsing_exception");
+        assert_eq!(body["status"], 400);
+        let connection = tokio::time::timeout(Duration::from_millis(50), listener.accept()).await;
+        assert!(connection.is_err(), "OpenSearch was connected to");
+    }
+
+    /// Mock OpenSearch node answering a single request, and handing it over as it was received
+    async fn recording_upstream() -> (SocketAddr, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::task::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = read_request_head(&mut stream).await;
+            // The rest of the body, as long as told by its content-length
+            let (head, body) = request.split_once("\r\n\r\n").unwrap();
+            let length: usize = head
+                .lines()
+                .find_map(|line| {
+                    line.to_ascii_lowercase()
+                        .strip_prefix("content-length: ")?
+                        .parse()
+                        .ok()
+                })
+                .unwrap_or(0);
+            let mut rest = vec![0; length - body.len()];
+            stream.read_exact(&mut rest).await.unwrap();
+            request.push_str(&String::from_utf8_lossy(&rest));
+            stream.write_all(OK.as_bytes()).await.unwrap();
+            sender.send(request).unwrap();
+        });
+        (addr, receiver)
+    }
+
+    #[tokio::test]
+    async fn chunked_request_is_forwarded_with_its_length() {
+        let (upstream, request) = recording_upstream().await;
+        let proxy = TestProxy::new(&["--upstream", &upstream.to_string()]);
+        let req = Request::post("/_bulk")
+            .header("content-type", "application/x-ndjson")
+            .header("transfer-encoding", "chunked")
+            .body(Bytes::from("{\"delete\": {\"_id\": \"1\"}}\n"))
+            .unwrap();
+        let res = proxy.handle(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let request = request.await.unwrap().to_ascii_lowercase();
+        assert!(request.contains("content-length: 25\r\n"), "{}", request);
+        assert!(!request.contains("transfer-encoding"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn forwarded_bulk_operations_are_counted() {
+        let (upstream, request) = recording_upstream().await;
+        let proxy = TestProxy::new(&["--upstream", &upstream.to_string()]);
+        let body = "{\"index\": {\"_id\": \"1\"}}\n{\"Description\": \"fire\"}\n\
+            {\"delete\": {\"_id\": \"2\"}}\n";
+        let req = Request::post("/_bulk")
+            .header("content-type", "application/x-ndjson")
+            .body(Bytes::from(body))
+            .unwrap();
+        proxy.handle(req).await;
+        assert!(request.await.unwrap().ends_with(body));
+        let stats = &proxy.stats;
+        assert_eq!(stats.bulk_index_count.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.bulk_delete_count.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.bulk_update_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn root_info_is_emulated_when_enabled() {
+        let upstream = closed_port().await;
+        let proxy = TestProxy::new(&[
+            "--upstream",
+            &upstream,
+            "--emulate-root-info",
+            "--root-info-version",
+            "2.13.0",
+        ]);
+        let res = proxy.handle(get("/")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["version"]["number"], "2.13.0");
+        // Forwarded otherwise
+        let proxy = TestProxy::new(&["--upstream", &upstream, "--upstream-retries", "0"]);
+        let res = proxy.handle(get("/")).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    /// Search with `X-TinyQ-Explain: true`
+    fn explained_search(body: Value) -> Request<Bytes> {
+        Request::post("/_search")
+            .header("content-type", "application/json")
+            .header("x-tinyq-explain", "true")
+            .body(Bytes::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn explain_header_tells_why_a_search_was_forwarded() {
+        let upstream = mock_upstream(vec![Some(OK)]).await.to_string();
+        let proxy = TestProxy::new(&["--upstream", &upstream]);
+        let search = json!({"query": {"multi_match": {"query": "fire"}}});
+        let res = proxy.handle(explained_search(search.clone())).await;
+        assert_eq!(res.headers()["x-tinyq-decision"], "outcome=emulated");
+        // Same body as without explaining
+        let explained = body_json(res).await;
+        let res = proxy.post("/_search", search).await;
+        assert!(!res.headers().contains_key("x-tinyq-decision"));
+        let mut plain = body_json(res).await;
+        plain["took"] = explained["took"].clone();
+        assert_eq!(plain, explained);
+
+        let res = proxy
+            .handle(explained_search(json!({"query": {"fuzzy": {}}})))
+            .await;
+        let decision = res.headers()["x-tinyq-decision"].to_str().unwrap();
+        assert!(
+            decision.starts_with("outcome=forwarded reason="),
+            "{}",
+            decision
+        );
+        assert!(decision.contains("fuzzy"), "{}", decision);
+    }
+
+    /// Serve the monitoring website of the configuration on an ephemeral port
+    fn serve_monitoring(config: Config) -> SocketAddr {
+        let app = monitoring_app(Arc::new(Stats::default()), Arc::new(config)).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        });
+        addr
+    }
+
+    /// Response of the monitoring website to a GET with the headers
+    async fn get_monitoring(
+        addr: SocketAddr,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Response<Incoming> {
+        let client: Client<HttpConnector, Full<Bytes>> =
+            Client::builder(TokioExecutor::new()).build_http();
+        let mut req = Request::get(format!("http://{}{}", addr, path));
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        client
+            .request(req.body(Full::new(Bytes::new())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn monitoring_responses_are_gzipped_on_request() {
+        let addr = serve_monitoring(test_config(&[]));
+        let res = get_monitoring(addr, "/stats.json", &[("accept-encoding", "gzip")]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-encoding"], "gzip");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let stats: Value = serde_json::from_reader(flate2::read::GzDecoder::new(&*body)).unwrap();
+        assert_eq!(stats["search_queries_success_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn search_without_an_index_searches_all_of_them() {
+        let store = DocumentStore::new(vec![
+            json!({"_index": "songs", "Description": "Through the fire"}),
+            json!({"_index": "poems", "Description": "Fire and ice"}),
+            json!({"_index": "poems", "Description": "The road not taken"}),
+        ]);
+        let proxy = TestProxy::with_store(&["--standalone"], store);
+        let res = proxy
+            .post(
+                "/_search",
+                json!({"query": {"multi_match": {"query": "fire"}}}),
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let response = body_json(res).await;
+        let mut indices: Vec<&str> = response["hits"]["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["_index"].as_str().unwrap())
+            .collect();
+        indices.sort();
+        assert_eq!(indices, ["poems", "songs"]);
+    }
+
+    #[tokio::test]
+    async fn unsupported_search_gives_not_implemented_in_standalone_mode() {
+        let proxy = TestProxy::new(&["--standalone"]);
+        let res = proxy
+            .post(
+                "/_search",
+                json!({"query": {"fuzzy": {"Description": "fier"}}}),
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+        let body = body_json(res).await;
+        assert_eq!(body["error"]["type"], "not_implemented_exception");
+        let reason = body["error"]["reason"].as_str().unwrap();
+        assert!(reason.contains("fuzzy"), "{}", reason);
+        assert_eq!(body["status"], 501);
+    }
+
+    /// Send the raw request to the proxy, the whole response as text
+    async fn raw_request(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn body_over_the_limit_gives_payload_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        let proxy = TestProxy::new(&["--upstream", &upstream, "--max-body-bytes", "16"]);
+        let addr = proxy.listen().await;
+        // Announced length over the limit
+        let response = raw_request(
+            addr,
+            b"POST /_bulk HTTP/1.1\r\nhost: tinyq\r\ncontent-length: 17\r\n\
+                connection: close\r\n\r\n{\"delete\": {}}\n\n\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        // Chunked, only over the limit once read
+        let response = raw_request(
+            addr,
+            b"POST /_search HTTP/1.1\r\nhost: tinyq\r\ntransfer-encoding: chunked\r\n\
+                connection: close\r\n\r\n10\r\n{\"query\": {}}   \r\n2\r\n{}\r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        let connection = tokio::time::timeout(Duration::from_millis(50), listener.accept()).await;
+        assert!(connection.is_err(), "OpenSearch was connected to");
+    }
+
+    #[tokio::test]
+    async fn scroll_search_is_forwarded_even_when_supported() {
+        let upstream = mock_upstream(vec![Some(OK), Some(OK)]).await.to_string();
+        let proxy = TestProxy::new(&["--upstream", &upstream]);
+        let res = proxy
+            .post("/_search?scroll=1m", json!({"query": {"match_all": {}}}))
+            .await;
+        assert_eq!(body_bytes(res).await, "{}");
+        let res = proxy
+            .post(
+                "/_search/scroll",
+                json!({"scroll": "1m", "scroll_id": "abc"}),
+            )
+            .await;
+        assert_eq!(body_bytes(res).await, "{}");
+        let stats = &proxy.stats;
+        assert_eq!(
+            stats.search_queries_success_count.load(Ordering::Relaxed),
+            0
+        );
+        assert_eq!(stats.scroll_requests_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn config_shows_the_upstream_but_not_the_credentials() {
+        let addr = serve_monitoring(test_config(&[
+            "--upstream",
+            "10.0.0.7:9200",
+            "--upstream-auth",
+            "Basic c2VjcmV0OnNlY3JldA==",
+        ]));
+        let res = get_monitoring(addr, "/config", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let config: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(config["upstream"], json!(["10.0.0.7:9200"]));
+        assert_eq!(config["upstream_auth"], "<redacted>");
+        assert!(!String::from_utf8_lossy(&body).contains("c2VjcmV0"));
+    }
+
+    #[tokio::test]
+    async fn expect_continue_is_answered_before_the_body() {
+        let (upstream, request) = recording_upstream().await;
+        let proxy = TestProxy::new(&["--upstream", &upstream.to_string()]);
+        let addr = proxy.listen().await;
+        let body = "{\"delete\": {\"_id\": \"1\"}}\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "POST /_bulk HTTP/1.1\r\nhost: tinyq\r\ncontent-type: application/x-ndjson\r\n\
+                content-length: {}\r\nexpect: 100-continue\r\nconnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        // Like curl, the body is only sent once told to continue
+        let interim = read_request_head(&mut stream).await;
+        assert!(
+            interim.starts_with("HTTP/1.1 100 Continue\r\n"),
+            "{}",
+            interim
+        );
+        stream.write_all(body.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        // Met by the proxy, not passed on
+        let request = request.await.unwrap().to_ascii_lowercase();
+        assert!(!request.contains("expect:"), "{}", request);
+        assert!(request.ends_with(body), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn ndjson_search_is_rejected_with_a_hint() {
+        let proxy = TestProxy::new(&["--standalone"]);
+        let req = Request::post("/_search")
+            .header("content-type", "application/x-ndjson; charset=UTF-8")
+            .body(Bytes::from("{}\n{\"query\": {\"match_all\": {}}}\n"))
+            .unwrap();
+        let res = proxy.handle(req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(res).await;
+        assert_eq!(body["error"]["type"], "illegal_argument_exception");
+        let reason = body["error"]["reason"].as_str().unwrap();
+        assert!(
+            reason.contains("ndjson") && reason.contains("_msearch"),
+            "{}",
+            reason
+        );
+        // Without a Content-Type, the body is taken as JSON
+        let req = Request::post("/_search")
+            .body(Bytes::from("{\"query\": {\"match_all\": {}}}"))
+            .unwrap();
+        assert_eq!(proxy.handle(req).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reloaded_documents_are_searched() {
+        let path = std::env::temp_dir().join(format!("tinyq-reload-{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{"Description": "Through the fire"}]"#).unwrap();
+        let documents = path.to_str().unwrap();
+        let store = DocumentStore::load(&path).unwrap();
+        let proxy = TestProxy::with_store(
+            &["--standalone", "--documents", documents, "--allow-reload"],
+            store,
+        );
+        let search = json!({"query": {"multi_match": {"query": "wall"}}});
+        let res = proxy.post("/_search", search.clone()).await;
+        assert_eq!(body_json(res).await["hits"]["total"]["value"], 0);
+
+        std::fs::write(
+            &path,
+            r#"[{"Description": "Through the fire"}, {"Description": "To the wall"}]"#,
+        )
+        .unwrap();
+        let res = proxy.post(RELOAD_PATH, Value::Null).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["documents_count"], 2);
+        // Not served from the cache of the previous documents
+        let res = proxy.post("/_search", search).await;
+        assert_eq!(body_json(res).await["hits"]["total"]["value"], 1);
+
+        // Broken file, the documents stay
+        std::fs::write(&path, "[{").unwrap();
+        let res = proxy.post(RELOAD_PATH, Value::Null).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body_json(res).await["error"]["type"], "reload_failed");
+        assert_eq!(proxy.store.get().documents().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn configured_headers_are_added_to_emulated_responses() {
+        let upstream = mock_upstream(vec![Some(OK)]).await.to_string();
+        let proxy = TestProxy::new(&[
+            "--upstream",
+            &upstream,
+            "--response-header",
+            "X-Served-By: tinyq",
+            "--response-header",
+            "Cache-Control: no-store",
+            "--response-header",
+            "Content-Type: text/plain",
+        ]);
+        let addr = proxy.listen().await;
+        let client: Client<HttpConnector, Full<Bytes>> =
+            Client::builder(TokioExecutor::new()).build_http();
+        let search = json!({"query": {"match_all": {}}}).to_string();
+        let req = Request::post(format!("http://{}/_search", addr))
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(search)))
+            .unwrap();
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-served-by"], "tinyq");
+        assert_eq!(res.headers()["cache-control"], "no-store");
+        // Set by the handler, kept
+        assert_eq!(
+            res.headers()["content-type"],
+            "application/json; charset=UTF-8"
+        );
+        // OpenSearch responses are passed as they are
+        let req = Request::get(format!("http://{}/_cluster/settings", addr))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let res = client.request(req).await.unwrap();
+        assert!(!res.headers().contains_key("x-served-by"));
+    }
+
+    #[test]
+    fn configured_headers_replace_the_existing_ones_when_overriding() {
+        let added = [parse_response_header("Content-Type: text/plain").unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        add_response_headers(&mut headers, &added, false);
+        assert_eq!(headers["content-type"], "application/json");
+        add_response_headers(&mut headers, &added, true);
+        assert_eq!(headers["content-type"], "text/plain");
+        assert_eq!(headers.get_all("content-type").iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn gzip_expanding_past_the_limit_is_not_inflated() {
+        let (upstream, request) = recording_upstream().await;
+        let proxy = TestProxy::new(&[
+            "--upstream",
+            &upstream.to_string(),
+            "--max-body-bytes",
+            "65536",
+        ]);
+        // A few kilobytes, 10 MiB once decompressed
+        let mut body = b"{\"delete\": {\"_id\": \"1\"}}\n".to_vec();
+        body.resize(10 * 1024 * 1024, b'\n');
+        let bomb = Bytes::from(encoding::encode(encoding::Encoding::Gzip, &body));
+        assert!(bomb.len() < 65536);
+        let gzipped = |path: &str| {
+            Request::post(path)
+                .header("content-type", "application/json")
+                .header("content-encoding", "gzip")
+                .body(bomb.clone())
+                .unwrap()
+        };
+        let res = proxy.handle(gzipped("/_search")).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Forwarded as it is, without counting its operations
+        let res = proxy.handle(gzipped("/_bulk")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(request.await.unwrap().contains("content-encoding: gzip"));
+        assert_eq!(proxy.stats.bulk_delete_count.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn emulated_search_requires_the_configured_credentials() {
+        let auth = "Basic dXNlcjpwYXNz";
+        let proxy = TestProxy::new(&["--standalone", "--upstream-auth", auth]);
+        let search = |authorization: Option<&str>| {
+            let mut req = Request::post("/_search");
+            if let Some(authorization) = authorization {
+                req = req.header("authorization", authorization);
+            }
+            req.body(Bytes::from("{}")).unwrap()
+        };
+        let res = proxy.handle(search(None)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = body_json(res).await;
+        assert!(body["error"]["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("missing authentication credentials"));
+        let res = proxy.handle(search(Some("Basic b3RoZXI6d3Jvbmc="))).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let body = body_json(res).await;
+        assert!(body["error"]["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("unable to authenticate user"));
+        let res = proxy.handle(search(Some(auth))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body_json(res).await["hits"]["total"]["value"], 3);
     }
 }
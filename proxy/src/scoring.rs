@@ -0,0 +1,147 @@
+//! BM25 relevance scoring of the hits, the OpenSearch default similarity.
+
+use crate::analyze::normalize;
+use crate::filter::{field_tokens, Filter};
+use serde_json::Value;
+
+/// Term frequency saturation: how quickly repeated terms stop adding to the score
+pub const K1: f64 = 1.2;
+
+/// Document length normalization: 0.0 ignores the length, 1.0 fully normalizes by it
+pub const B: f64 = 0.75;
+
+/// Score of hits of queries without text clauses (match_all, term, ...), as in constant score queries
+pub const CONSTANT_SCORE: f64 = 1.0;
+
+/// Scores documents for a query, with the corpus statistics collected up front.
+pub struct Scorer<'a> {
+    clauses: Vec<ClauseScorer<'a>>,
+    case_sensitive: bool,
+}
+
+/// Statistics of the corpus for one text clause of the query
+struct ClauseScorer<'a> {
+    fields: &'a [String],
+    terms: Vec<String>,
+    // The terms are scored together, as the occurrences of the phrase they make
+    phrase: bool,
+    // Inverse document frequency of each of the terms
+    idfs: Vec<f64>,
+    avg_length: f64,
+}
+
+impl<'a> Scorer<'a> {
+    /// Collect statistics of the corpus needed by the text clauses of the query.
+    /// Queries without text clauses don't scan the corpus.
+    pub fn new(
+        corpus: &[Value],
+        query: impl IntoIterator<Item = &'a Filter>,
+        case_sensitive: bool,
+    ) -> Self {
+        let clauses = query
+            .into_iter()
+            .filter_map(|filter| {
+                let (terms, fields) = filter.text_query()?;
+                let phrase = matches!(filter, Filter::MatchPhrase { .. });
+                Some(ClauseScorer::new(
+                    corpus,
+                    terms,
+                    fields,
+                    phrase,
+                    case_sensitive,
+                ))
+            })
+            .collect();
+        Scorer {
+            clauses,
+            case_sensitive,
+        }
+    }
+
+    /// Score of the document: sum of the scores of the text clauses
+    pub fn score(&self, source: &Value) -> f64 {
+        if self.clauses.is_empty() {
+            return CONSTANT_SCORE;
+        }
+        self.clauses
+            .iter()
+            .map(|clause| clause.score(source, self.case_sensitive))
+            .sum()
+    }
+}
+
+impl<'a> ClauseScorer<'a> {
+    fn new(
+        corpus: &[Value],
+        terms: &[String],
+        fields: &'a [String],
+        phrase: bool,
+        case_sensitive: bool,
+    ) -> Self {
+        let terms: Vec<String> = terms
+            .iter()
+            .map(|term| normalize(term, case_sensitive).into_owned())
+            .collect();
+        let mut doc_freqs = vec![0usize; terms.len()];
+        let mut total_length = 0;
+        let mut docs_with_field = 0;
+        for source in corpus {
+            let tokens = field_tokens(source, fields, case_sensitive);
+            if tokens.is_empty() {
+                continue;
+            }
+            docs_with_field += 1;
+            total_length += tokens.len();
+            for (term, doc_freq) in terms.iter().zip(doc_freqs.iter_mut()) {
+                if tokens.contains(term) {
+                    *doc_freq += 1;
+                }
+            }
+        }
+        let doc_count = corpus.len() as f64;
+        let idfs = doc_freqs
+            .iter()
+            .map(|&doc_freq| {
+                let doc_freq = doc_freq as f64;
+                (1.0 + (doc_count - doc_freq + 0.5) / (doc_freq + 0.5)).ln()
+            })
+            .collect();
+        let avg_length = if docs_with_field == 0 {
+            1.0
+        } else {
+            total_length as f64 / docs_with_field as f64
+        };
+        ClauseScorer {
+            fields,
+            terms,
+            phrase,
+            idfs,
+            avg_length,
+        }
+    }
+
+    fn score(&self, source: &Value, case_sensitive: bool) -> f64 {
+        let tokens = field_tokens(source, self.fields, case_sensitive);
+        let length_norm = 1.0 - B + B * tokens.len() as f64 / self.avg_length;
+        let saturate = |freq: f64| freq * (K1 + 1.0) / (freq + K1 * length_norm);
+        // Like in Lucene, the idf of a phrase is the sum of the idfs of its terms
+        if self.phrase {
+            if self.terms.is_empty() {
+                return 0.0;
+            }
+            let phrase_freq = tokens
+                .windows(self.terms.len())
+                .filter(|window| *window == self.terms.as_slice())
+                .count() as f64;
+            return self.idfs.iter().sum::<f64>() * saturate(phrase_freq);
+        }
+        self.terms
+            .iter()
+            .zip(&self.idfs)
+            .map(|(term, idf)| {
+                let term_freq = tokens.iter().filter(|token| *token == term).count() as f64;
+                idf * saturate(term_freq)
+            })
+            .sum()
+    }
+}
@@ -0,0 +1,2923 @@
+use crate::aggregation::{parse_aggregations, Aggregation};
+use crate::alias::FieldAliases;
+use crate::analyze::{normalize, tokenize};
+use crate::filter::{wildcard_regex, BoolQuery, Filter};
+use crate::geo::{parse_distance, parse_point, GeoFilter};
+use crate::highlight::{parse_highlight, Highlight};
+use crate::index::{intersect, InvertedIndex};
+use crate::scoring::Scorer;
+use crate::store::DocumentStore;
+use percent_encoding::percent_decode_str;
+use serde_json::json;
+use serde_json::Value;
+use serde_json::Value::{Array, Bool, Object};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+/// Raw _search request, as received over HTTP.
+pub struct SearchInput<'a> {
+    /// Indices from the URL path, like `logs-*,metrics`. None for all the indices.
+    pub index: Option<&'a str>,
+    /// URL query string (without the leading `?`)
+    pub query: &'a str,
+    /// Request body, expected to be JSON
+    pub body: &'a [u8],
+}
+
+/// Why a request isn't emulated
+#[derive(Debug)]
+pub enum SearchError {
+    /// Valid request using something we don't emulate, OpenSearch can handle it
+    Unsupported(String),
+    /// Body isn't valid JSON, OpenSearch would reject it just the same
+    Malformed(serde_json::Error),
+}
+
+impl From<String> for SearchError {
+    fn from(reason: String) -> Self {
+        SearchError::Unsupported(reason)
+    }
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::Unsupported(reason) => write!(f, "{}", reason),
+            SearchError::Malformed(err) => write!(f, "malformed JSON body: {}", err),
+        }
+    }
+}
+
+/// Settings of the emulation, shared by all requests.
+pub struct SearchSettings {
+    /// Upper bound for the time spent scanning the corpus, regardless of the request `timeout`
+    pub max_timeout: Duration,
+    /// Match text queries exactly instead of ignoring case like the default analyzer
+    pub case_sensitive: bool,
+    /// Field names queried by the clients which are stored under another name
+    pub field_aliases: FieldAliases,
+    /// Number of hits returned when the request doesn't specify `size`,
+    /// like for the `match_all` of a dashboard populating a table
+    pub default_size: usize,
+    /// `took` reported by all the responses instead of the actual time, for deterministic tests
+    pub fixed_took: Option<u64>,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings {
+            max_timeout: Duration::from_secs(30),
+            case_sensitive: false,
+            field_aliases: FieldAliases::default(),
+            default_size: DEFAULT_SIZE,
+            fixed_took: None,
+        }
+    }
+}
+
+/// Number of hits returned when the request doesn't specify `size`, like in OpenSearch
+pub const DEFAULT_SIZE: usize = 10;
+
+/// How many documents are scanned between checks of the deadline
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+
+/// Index of the documents of the corpus without an `_index` field
+pub const DEFAULT_INDEX: &str = "my-first-index";
+
+/// Version reported for the documents of the corpus without a `_version` field
+pub const DOCUMENT_VERSION: u64 = 5;
+
+/// Id reported for the documents of the corpus without an `_id` field, they all share it
+pub const DOCUMENT_ID: &str = "1";
+
+/// Fields of the documents which are metadata, reported next to the source rather than in it
+pub(crate) const METADATA_FIELDS: &[&str] = &["_index", "_id", "_version"];
+
+// Parsing _search request:
+
+#[derive(Debug, Default)]
+struct ParsedSearchRequest {
+    // Index name patterns (exact or with `*` wildcards) from the URL path,
+    // None for all the indices
+    indices: Option<Vec<String>>,
+    // Filters (kept in the order they were given) and clauses of the query,
+    // the whole query is a bool one
+    query: BoolQuery,
+    // Lucene-lite query from the `q` URL option
+    q: Option<String>,
+    timeout: Option<Duration>,
+    // Pagination, from the body or URL options (body takes precedence)
+    from: Option<usize>,
+    size: Option<usize>,
+    source_includes: Vec<String>,
+    source_excludes: Vec<String>,
+    // `_source: false`, hits carry no source at all
+    source_disabled: bool,
+    rescore: Option<Rescore>,
+    // Sort keys as (field, ascending), insertion order when empty
+    sort: Vec<(String, bool)>,
+    // Sort values of the last hit of the previous page, only the hits after it are returned
+    search_after: Option<Vec<Value>>,
+    // Named aggregations over all the matching documents, not just the returned page
+    aggregations: Vec<(String, Aggregation)>,
+    // Aggregation names get prefixed with their type in the response
+    typed_keys: bool,
+    // From the body or URL options (body takes precedence)
+    track_total_hits: TrackTotalHits,
+    // `hits.total` as a bare number, the legacy (Elasticsearch 6) shape
+    rest_total_hits_as_int: bool,
+    highlight: Option<Highlight>,
+    // `lenient` URL option: numeric strings compare as numbers in term and range clauses
+    lenient: bool,
+    // Hits report their `_version`, from the body or URL options (body takes precedence)
+    version: bool,
+}
+
+/// How `hits.total` is counted, from `track_total_hits`
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+enum TrackTotalHits {
+    /// `true` (or -1): the exact number of matches
+    #[default]
+    Exact,
+    /// Up to the threshold, reported as a lower bound beyond it
+    UpTo(usize),
+    /// `false`: only the returned hits are reported, as a lower bound
+    Disabled,
+}
+
+impl TrackTotalHits {
+    /// `hits.total` value and relation for the number of matches and of returned hits
+    fn total(self, matches: usize, returned: usize) -> Value {
+        let (value, relation) = match self {
+            TrackTotalHits::UpTo(threshold) if matches > threshold => (threshold, "gte"),
+            TrackTotalHits::Exact | TrackTotalHits::UpTo(_) => (matches, "eq"),
+            TrackTotalHits::Disabled => (returned, "gte"),
+        };
+        json!({ "value": value, "relation": relation })
+    }
+}
+
+/// Second scoring phase, applied to the top `window_size` hits only.
+#[derive(Debug)]
+struct Rescore {
+    window_size: usize,
+    query: Vec<Filter>,
+    query_weight: f64,
+    rescore_query_weight: f64,
+}
+
+impl ParsedSearchRequest {
+    /// The request with its defaults filled in, as text, see `normalized_search`.
+    /// The timeout is left out: timed out results aren't cached.
+    fn normalized(mut self, settings: &SearchSettings) -> String {
+        self.from.get_or_insert(0);
+        self.size.get_or_insert(settings.default_size);
+        self.timeout = None;
+        format!("{:?}", self)
+    }
+
+    /// Ids of the documents which can match the text clauses among the must/filter ones,
+    /// None without such clauses: all the documents have to be scanned then
+    fn candidates(&self, index: &InvertedIndex) -> Option<Vec<usize>> {
+        self.query
+            .must
+            .iter()
+            .chain(&self.query.filters)
+            .filter_map(Filter::text_query)
+            .map(|(terms, fields)| index.lookup(terms, fields))
+            .reduce(intersect)
+    }
+
+    /// Replace the aliased field names, wherever the request refers to fields, with the stored ones
+    fn resolve_aliases(&mut self, aliases: &FieldAliases) {
+        self.query.resolve_aliases(aliases);
+        if let Some(rescore) = &mut self.rescore {
+            rescore
+                .query
+                .iter_mut()
+                .for_each(|filter| filter.resolve_aliases(aliases));
+        }
+        self.source_includes
+            .iter_mut()
+            .chain(&mut self.source_excludes)
+            .chain(self.sort.iter_mut().map(|(field, _)| field))
+            .for_each(|field| aliases.resolve(field));
+        self.aggregations
+            .iter_mut()
+            .for_each(|(_, aggregation)| aggregation.resolve_aliases(aliases));
+        if let Some(highlight) = &mut self.highlight {
+            highlight.resolve_aliases(aliases);
+        }
+    }
+
+    /// Whether the document matches the query: all the filters and the bool clauses
+    fn matches(&self, source: &Value, case_sensitive: bool) -> bool {
+        let index = document_index(source);
+        self.indices.as_ref().is_none_or(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| wildcard_matches(pattern, index))
+        }) && self.query.matches(source, case_sensitive, self.lenient)
+    }
+}
+
+/// Index of the document: its `_index` field, or the default index
+pub(crate) fn document_index(source: &Value) -> &str {
+    source
+        .get("_index")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_INDEX)
+}
+
+/// Id of the document: its `_id` field, or the shared default id
+pub(crate) fn document_id(source: &Value) -> &str {
+    source
+        .get("_id")
+        .and_then(Value::as_str)
+        .unwrap_or(DOCUMENT_ID)
+}
+
+/// Version of the document: its `_version` field, or the default version
+pub(crate) fn document_version(source: &Value) -> u64 {
+    source
+        .get("_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(DOCUMENT_VERSION)
+}
+
+// Parse index expression of the URL path like `logs-*,metrics`,
+// None when it covers all the indices (`_all`, `*`)
+fn parse_indices(expression: &str) -> Result<Option<Vec<String>>, String> {
+    let expression = percent_decode_str(expression).decode_utf8_lossy();
+    let mut patterns = Vec::new();
+    for pattern in expression.split(',') {
+        match pattern {
+            "_all" | "*" => return Ok(None),
+            _ if pattern.starts_with('-') => {
+                return Err(format!("unimplemented index exclusion: {}", pattern));
+            }
+            _ => patterns.push(pattern.to_string()),
+        }
+    }
+    Ok(Some(patterns))
+}
+
+/// Whether the name matches the pattern, where `*` stands for any (possibly empty) string
+pub(crate) fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // There's always a first part, empty if the pattern starts with `*`
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        let Some(position) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[position + part.len()..];
+    }
+    rest.ends_with(last)
+}
+
+/// URL options accepted as no-ops: they don't change anything in our emulated responses,
+/// like shard routing or caching on a single-node, single-shard cluster.
+/// Any other unknown option still makes the request fall through to OpenSearch.
+const IGNORED_OPTIONS: &[&str] = &[
+    "ignore_unavailable",
+    "allow_no_indices",
+    "expand_wildcards",
+    "preference",
+    "routing",
+    "request_cache",
+    "allow_partial_search_results",
+    "batched_reduce_size",
+    "pre_filter_shard_size",
+    "max_concurrent_shard_requests",
+    "ccs_minimize_roundtrips",
+    "error_trace",
+];
+
+/// URL options asking for something only OpenSearch can do, like keeping a scroll context:
+/// requests with them are always forwarded
+const FORWARDED_OPTIONS: &[&str] = &["scroll", "explain", "seq_no_primary_term"];
+
+fn parse_options(options: &[Vec<String>], parsed: &mut ParsedSearchRequest) -> Result<(), String> {
+    for option in options {
+        match option.first().map(String::as_str) {
+            Some(option) if FORWARDED_OPTIONS.contains(&option) => {
+                return Err(format!("unimplemented URL option {}", option))
+            }
+            Some(option) if IGNORED_OPTIONS.contains(&option) => {}
+            Some("track_total_hits") => {
+                // Without a value, like `?track_total_hits`, it means true
+                parsed.track_total_hits =
+                    parse_track_total_hits(option.get(1).map_or("true", String::as_str))?;
+            }
+            Some("timeout") => {
+                let Some(timeout) = option.get(1) else {
+                    return Err("missing value for URL option timeout".to_string());
+                };
+                parsed.timeout = parse_time_value(timeout)?;
+            }
+            Some("rest_total_hits_as_int") => {
+                parsed.rest_total_hits_as_int = match option.get(1).map(String::as_str) {
+                    None | Some("true") => true,
+                    Some("false") => false,
+                    Some(value) => {
+                        return Err(format!(
+                            "unsupported rest_total_hits_as_int value {}",
+                            value
+                        ))
+                    }
+                };
+            }
+            Some("typed_keys") => {
+                parsed.typed_keys = match option.get(1).map(String::as_str) {
+                    None | Some("true") => true,
+                    Some("false") => false,
+                    Some(value) => return Err(format!("unsupported typed_keys value {}", value)),
+                };
+            }
+            Some("lenient") => {
+                parsed.lenient = match option.get(1).map(String::as_str) {
+                    None | Some("true") => true,
+                    Some("false") => false,
+                    Some(value) => return Err(format!("unsupported lenient value {}", value)),
+                };
+            }
+            Some("version") => {
+                parsed.version = match option.get(1).map(String::as_str) {
+                    None | Some("true") => true,
+                    Some("false") => false,
+                    Some(value) => return Err(format!("unsupported version value {}", value)),
+                };
+            }
+
+            Some("from") | Some("size") => {
+                let Some(option_value) = option.get(1) else {
+                    return Err(format!("missing value for URL option {}", option[0]));
+                };
+                let option_value: usize = option_value
+                    .parse()
+                    .map_err(|_| format!("unsupported {} value {}", option[0], option_value))?;
+                if option[0] == "from" {
+                    parsed.from = Some(option_value);
+                } else {
+                    parsed.size = Some(option_value);
+                }
+            }
+            Some("q") => {
+                let Some(q) = option.get(1) else {
+                    return Err("missing value for URL option q".to_string());
+                };
+                parsed.q = Some(q.to_string());
+            }
+
+            // Older clients send the singular (legacy) forms, treat them exactly like the plural ones
+            Some("_source_includes") | Some("_source_include") => {
+                parsed
+                    .source_includes
+                    .extend(parse_source_fields_option(option)?);
+            }
+            Some("_source_excludes") | Some("_source_exclude") => {
+                parsed
+                    .source_excludes
+                    .extend(parse_source_fields_option(option)?);
+            }
+
+            Some(option) => return Err(format!("unsupported URL option {}", option)),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+// Parse OpenSearch time value like 30000ms or 1m, -1 meaning no timeout
+fn parse_time_value(value: &str) -> Result<Option<Duration>, String> {
+    if value == "-1" {
+        return Ok(None);
+    }
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in time value {}", value))?;
+    let (amount, unit) = value.split_at(unit_start);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("unsupported time value {}", value))?;
+    let duration = match unit {
+        "d" => Duration::from_secs(amount * 24 * 60 * 60),
+        "h" => Duration::from_secs(amount * 60 * 60),
+        "m" => Duration::from_secs(amount * 60),
+        "s" => Duration::from_secs(amount),
+        "ms" => Duration::from_millis(amount),
+        "micros" => Duration::from_micros(amount),
+        "nanos" => Duration::from_nanos(amount),
+        _ => return Err(format!("unsupported time unit in {}", value)),
+    };
+    Ok(Some(duration))
+}
+
+// Parse Lucene-lite query from the `q` URL option: `term`, `term1 term2` or `field:term`
+fn parse_query_string(q: &str, parsed: &mut ParsedSearchRequest) -> Result<(), String> {
+    if q.contains(['(', ')', '"', '*', '?', '[', ']', '{', '}']) {
+        return Err(format!("unimplemented q URL option syntax: {}", q));
+    }
+    let filter = match q.split_once(':') {
+        Some((field, terms)) => Filter::MultiMatch {
+            terms: tokenize(terms),
+            fields: vec![field.to_string()],
+        },
+        None => Filter::MultiMatch {
+            terms: tokenize(q),
+            fields: Vec::new(),
+        },
+    };
+    parsed.query.must.push(filter);
+    Ok(())
+}
+
+// Parse comma-separated list of fields like _source_includes=Description,Title
+fn parse_source_fields_option(option: &[String]) -> Result<Vec<String>, String> {
+    let Some(fields) = option.get(1) else {
+        return Err(format!("missing value for URL option {}", option[0]));
+    };
+    Ok(fields
+        .split(',')
+        .filter(|field| !field.is_empty())
+        .map(|field| field.to_string())
+        .collect())
+}
+
+/// Query clauses and body features we can emulate, reported at startup
+pub const SUPPORTED_FEATURES: &[&str] = &[
+    "match_all",
+    "bool (filter/must/must_not/should, nested)",
+    "multi_match (fields)",
+    "match",
+    "match_phrase",
+    "query_string (terms, AND/OR)",
+    "term",
+    "terms",
+    "wildcard",
+    "prefix",
+    "exists",
+    "range (numeric)",
+    "geo_bounding_box",
+    "geo_distance",
+    "rescore",
+    "highlight",
+    "aggs (value_count)",
+    "from/size",
+    "sort",
+    "search_after",
+    "_source_includes/excludes",
+    "_source (includes/excludes/false)",
+    "q",
+];
+
+/// How deep bool queries can be nested in each other
+const MAX_BOOL_DEPTH: usize = 10;
+
+// Parse query filters like match_all or multi_match, within `depth` bool queries
+fn parse_filter(
+    value: &Value,
+    filter: &serde_json::Map<String, Value>,
+    filters: &mut Vec<Filter>,
+    depth: usize,
+) -> Result<(), String> {
+    let filter_keys: Vec<_> = filter.keys().collect();
+    if filter_keys == vec!["bool"] {
+        if depth >= MAX_BOOL_DEPTH {
+            return Err(format!(
+                "unimplemented query value - bool queries nested more than {} levels deep",
+                MAX_BOOL_DEPTH
+            ));
+        }
+        let mut nested = BoolQuery::default();
+        parse_bool_query(value, &mut nested, depth + 1)?;
+        filters.push(Filter::Bool(nested));
+    } else if filter_keys == vec!["match_all"] {
+        let Some(Object(match_all_filter)) = filter.get("match_all") else {
+            return Err(format!(
+                "unimplemented query value - unexpected match_all filter: {}",
+                value
+            ));
+        };
+        if !match_all_filter.keys().collect::<Vec<_>>().is_empty() {
+            return Err(format!(
+                "unimplemented query value - non-empty match_all filter: {}",
+                value
+            ));
+        }
+    } else if filter_keys == vec!["multi_match"] {
+        let Some(Object(multi_match_filter)) = filter.get("multi_match") else {
+            return Err(format!(
+                "unimplemented query value - unexpected multi_match filter: {}",
+                value
+            ));
+        };
+        let mut terms = Vec::new();
+        let mut fields = Vec::new();
+        for (filter_key, filter_value) in multi_match_filter {
+            match filter_key.as_str() {
+                "lenient" => {}
+                "fields" => fields = parse_multi_match_fields(filter_value)?,
+                "type" => {
+                    if *filter_value != "best_fields" {
+                        return Err(format!(
+                            "unimplemented multi_match type value: {}",
+                            filter_value
+                        ));
+                    }
+                }
+                "query" => {
+                    if let Value::String(filter_value) = filter_value {
+                        terms = tokenize(filter_value);
+                    } else {
+                        return Err(format!(
+                            "unimplemented multi_match query value: {}",
+                            filter_value
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "unimplemented multi_match parameter: {}",
+                        filter_key
+                    ));
+                }
+            }
+        }
+        filters.push(Filter::MultiMatch { terms, fields });
+    } else if filter_keys == vec!["match"] {
+        let Some(Object(match_filter)) = filter.get("match") else {
+            return Err(format!(
+                "unimplemented query value - unexpected match filter: {}",
+                value
+            ));
+        };
+        let Some((field, match_value)) = single_field(match_filter) else {
+            return Err(format!(
+                "unimplemented match value - expected single field: {}",
+                value
+            ));
+        };
+        let query = match match_value {
+            // Shorthand {"match": {"Description": "fire"}}
+            Value::String(query) => query.clone(),
+            Object(match_params) => {
+                let mut query = None;
+                for (param_key, param_value) in match_params {
+                    match param_key.as_str() {
+                        "query" => {
+                            let Value::String(param_value) = param_value else {
+                                return Err(format!(
+                                    "unimplemented match query value: {}",
+                                    param_value
+                                ));
+                            };
+                            query = Some(param_value.clone());
+                        }
+                        _ => {
+                            return Err(format!("unimplemented match parameter: {}", param_key));
+                        }
+                    }
+                }
+                query.ok_or_else(|| {
+                    format!("unimplemented match value - missing query: {}", value)
+                })?
+            }
+            _ => {
+                return Err(format!("unimplemented match value: {}", match_value));
+            }
+        };
+        filters.push(Filter::Match {
+            field: field.clone(),
+            terms: tokenize(&query),
+        });
+    } else if filter_keys == vec!["query_string"] {
+        let Some(Object(query_string_filter)) = filter.get("query_string") else {
+            return Err(format!(
+                "unimplemented query value - unexpected query_string filter: {}",
+                value
+            ));
+        };
+        let mut query = None;
+        let mut default_and = false;
+        for (param_key, param_value) in query_string_filter {
+            match (param_key.as_str(), param_value) {
+                ("query", Value::String(param_value)) => query = Some(param_value.as_str()),
+                // There are no wildcards to analyze, they're rejected
+                ("analyze_wildcard", Bool(_)) => {}
+                ("default_operator", Value::String(operator))
+                    if operator.eq_ignore_ascii_case("or") => {}
+                ("default_operator", Value::String(operator))
+                    if operator.eq_ignore_ascii_case("and") =>
+                {
+                    default_and = true;
+                }
+                ("query" | "analyze_wildcard" | "default_operator", _) => {
+                    return Err(format!(
+                        "unimplemented query_string {} value: {}",
+                        param_key, param_value
+                    ));
+                }
+                _ => {
+                    return Err(format!(
+                        "unimplemented query_string parameter: {}",
+                        param_key
+                    ));
+                }
+            }
+        }
+        let Some(query) = query else {
+            return Err(format!(
+                "unimplemented query_string value - missing query: {}",
+                value
+            ));
+        };
+        filters.extend(parse_query_string_query(query, default_and)?);
+    } else if filter_keys == vec!["match_phrase"] {
+        let Some(Object(match_phrase_filter)) = filter.get("match_phrase") else {
+            return Err(format!(
+                "unimplemented query value - unexpected match_phrase filter: {}",
+                value
+            ));
+        };
+        let Some((field, match_phrase_value)) = single_field(match_phrase_filter) else {
+            return Err(format!(
+                "unimplemented match_phrase value - expected single field: {}",
+                value
+            ));
+        };
+        let phrase = match match_phrase_value {
+            // Shorthand {"match_phrase": {"Description": "through the fire"}}
+            Value::String(phrase) => phrase.clone(),
+            Object(match_phrase_params) => {
+                let mut phrase = None;
+                for (param_key, param_value) in match_phrase_params {
+                    match param_key.as_str() {
+                        "query" => {
+                            let Value::String(param_value) = param_value else {
+                                return Err(format!(
+                                    "unimplemented match_phrase query value: {}",
+                                    param_value
+                                ));
+                            };
+                            phrase = Some(param_value.clone());
+                        }
+                        // Terms have to be adjacent, there's no proximity matching (yet)
+                        "slop" if param_value.as_u64() == Some(0) => {}
+                        _ => {
+                            return Err(format!(
+                                "unimplemented match_phrase parameter: {}",
+                                param_key
+                            ));
+                        }
+                    }
+                }
+                phrase.ok_or_else(|| {
+                    format!(
+                        "unimplemented match_phrase value - missing query: {}",
+                        value
+                    )
+                })?
+            }
+            _ => {
+                return Err(format!(
+                    "unimplemented match_phrase value: {}",
+                    match_phrase_value
+                ));
+            }
+        };
+        filters.push(Filter::MatchPhrase {
+            field: field.clone(),
+            terms: tokenize(&phrase),
+        });
+    } else if filter_keys == vec!["term"] {
+        let Some(Object(term_filter)) = filter.get("term") else {
+            return Err(format!(
+                "unimplemented query value - unexpected term filter: {}",
+                value
+            ));
+        };
+        let Some((field, term_value)) = single_field(term_filter) else {
+            return Err(format!(
+                "unimplemented term value - expected single field: {}",
+                value
+            ));
+        };
+        let term_value = match term_value {
+            // Longhand {"term": {"field": {"value": "x"}}}
+            Object(term_params) => {
+                let mut term_value = None;
+                for (param_key, param_value) in term_params {
+                    match param_key.as_str() {
+                        "value" => term_value = Some(param_value),
+                        _ => {
+                            return Err(format!("unimplemented term parameter: {}", param_key));
+                        }
+                    }
+                }
+                term_value
+                    .ok_or_else(|| format!("unimplemented term value - missing value: {}", value))?
+            }
+            _ => term_value,
+        };
+        if !(term_value.is_string() || term_value.is_number() || term_value.is_boolean()) {
+            return Err(format!("unimplemented term value: {}", term_value));
+        }
+        filters.push(Filter::Term {
+            field: field.clone(),
+            value: term_value.clone(),
+        });
+    } else if filter_keys == vec!["wildcard"] || filter_keys == vec!["prefix"] {
+        let kind = filter_keys[0].as_str();
+        let (field, pattern, case_insensitive) = parse_pattern_query(kind, value, &filter[kind])?;
+        if kind == "wildcard" {
+            filters.push(Filter::Wildcard {
+                field,
+                pattern: wildcard_regex(&pattern, case_insensitive)?,
+            });
+        } else {
+            filters.push(Filter::Prefix {
+                field,
+                prefix: normalize(&pattern, !case_insensitive).into_owned(),
+                case_insensitive,
+            });
+        }
+    } else if filter_keys == vec!["terms"] {
+        let Some(Object(terms_filter)) = filter.get("terms") else {
+            return Err(format!(
+                "unimplemented query value - unexpected terms filter: {}",
+                value
+            ));
+        };
+        let Some((field, Array(terms_values))) = single_field(terms_filter) else {
+            return Err(format!(
+                "unimplemented terms value - expected single field with an array: {}",
+                value
+            ));
+        };
+        let mut values = Vec::new();
+        for terms_value in terms_values {
+            let Value::String(terms_value) = terms_value else {
+                return Err(format!("unimplemented terms value: {}", terms_value));
+            };
+            values.push(terms_value.clone());
+        }
+        filters.push(Filter::Terms {
+            field: field.clone(),
+            values,
+        });
+    } else if filter_keys == vec!["exists"] {
+        let Some(Object(exists_filter)) = filter.get("exists") else {
+            return Err(format!(
+                "unimplemented query value - unexpected exists filter: {}",
+                value
+            ));
+        };
+        let mut field = None;
+        for (param_key, param_value) in exists_filter {
+            match param_key.as_str() {
+                "field" => {
+                    let Value::String(param_value) = param_value else {
+                        return Err(format!("unimplemented exists field: {}", param_value));
+                    };
+                    field = Some(param_value.clone());
+                }
+                _ => return Err(format!("unimplemented exists parameter: {}", param_key)),
+            }
+        }
+        let Some(field) = field else {
+            return Err(format!(
+                "unimplemented exists value - missing field: {}",
+                value
+            ));
+        };
+        filters.push(Filter::Exists { field });
+    } else if filter_keys == vec!["range"] {
+        let Some(Object(range_filter)) = filter.get("range") else {
+            return Err(format!(
+                "unimplemented query value - unexpected range filter: {}",
+                value
+            ));
+        };
+        let Some((field, Object(range_params))) = single_field(range_filter) else {
+            return Err(format!(
+                "unimplemented range value - expected single field: {}",
+                value
+            ));
+        };
+        let (mut gt, mut gte, mut lt, mut lte) = (None, None, None, None);
+        for (param_key, param_value) in range_params {
+            let bound = match param_key.as_str() {
+                "gt" => &mut gt,
+                "gte" => &mut gte,
+                "lt" => &mut lt,
+                "lte" => &mut lte,
+                // Date math and formats (format, time_zone, "now-1d") aren't emulated
+                _ => return Err(format!("unimplemented range parameter: {}", param_key)),
+            };
+            let Some(number) = param_value.as_f64() else {
+                return Err(format!(
+                    "unimplemented range {} value: {}",
+                    param_key, param_value
+                ));
+            };
+            *bound = Some(number);
+        }
+        filters.push(Filter::Range {
+            field: field.clone(),
+            gt,
+            gte,
+            lt,
+            lte,
+        });
+    } else if filter_keys == vec!["geo_bounding_box"] {
+        let Some(Object(geo_filter)) = filter.get("geo_bounding_box") else {
+            return Err(format!(
+                "unimplemented query value - unexpected geo_bounding_box filter: {}",
+                value
+            ));
+        };
+        let Some((field, Object(bounding_box))) = single_field(geo_filter) else {
+            return Err(format!(
+                "unimplemented geo_bounding_box value - expected single field: {}",
+                value
+            ));
+        };
+        let mut top_left = None;
+        let mut bottom_right = None;
+        for (box_key, box_value) in bounding_box {
+            match box_key.as_str() {
+                "top_left" => top_left = parse_point(box_value),
+                "bottom_right" => bottom_right = parse_point(box_value),
+                _ => {
+                    return Err(format!(
+                        "unimplemented geo_bounding_box parameter: {}",
+                        box_key
+                    ));
+                }
+            }
+        }
+        let (Some(top_left), Some(bottom_right)) = (top_left, bottom_right) else {
+            return Err(format!(
+                "unimplemented geo_bounding_box value - expected top_left and bottom_right points: {}",
+                value
+            ));
+        };
+        filters.push(Filter::Geo(GeoFilter::BoundingBox {
+            field: field.clone(),
+            top_left,
+            bottom_right,
+        }));
+    } else if filter_keys == vec!["geo_distance"] {
+        let Some(Object(geo_filter)) = filter.get("geo_distance") else {
+            return Err(format!(
+                "unimplemented query value - unexpected geo_distance filter: {}",
+                value
+            ));
+        };
+        let mut distance_meters = None;
+        let mut center = None;
+        for (filter_key, filter_value) in geo_filter {
+            match filter_key.as_str() {
+                "distance" => distance_meters = Some(parse_distance(filter_value)?),
+                "distance_type" => {
+                    // We always compute the arc distance
+                    if *filter_value != "arc" {
+                        return Err(format!(
+                            "unimplemented geo_distance distance_type: {}",
+                            filter_value
+                        ));
+                    }
+                }
+                "validation_method" | "ignore_unmapped" | "boost" | "_name" => {
+                    return Err(format!(
+                        "unimplemented geo_distance parameter: {}",
+                        filter_key
+                    ));
+                }
+                field => {
+                    if center.is_some() {
+                        return Err(format!(
+                            "unimplemented geo_distance value - expected single field: {}",
+                            value
+                        ));
+                    }
+                    let Some(point) = parse_point(filter_value) else {
+                        return Err(format!(
+                            "unimplemented geo_distance point: {}",
+                            filter_value
+                        ));
+                    };
+                    center = Some((field.to_string(), point));
+                }
+            }
+        }
+        let (Some(distance_meters), Some((field, center))) = (distance_meters, center) else {
+            return Err(format!(
+                "unimplemented geo_distance value - expected distance and point: {}",
+                value
+            ));
+        };
+        filters.push(Filter::Geo(GeoFilter::Distance {
+            field,
+            center,
+            distance_meters,
+        }));
+    } else {
+        return Err(format!(
+            "unimplemented query value - unexpected filter: {}",
+            value
+        ));
+    }
+
+    Ok(())
+}
+
+// Parse the Lucene syntax of a query_string query, only terms searched in all the fields
+// and combined all with OR or all with AND, like `fire limit` or `fire AND limit`.
+// Terms without an operator between them are combined with the default one.
+fn parse_query_string_query(query: &str, default_and: bool) -> Result<Vec<Filter>, String> {
+    // Grouping, phrases, wildcards, field prefixes, ranges, fuzziness, boosts, regexes, escapes
+    // and the other boolean operators
+    if query.contains([
+        '(', ')', '"', '*', '?', ':', '[', ']', '{', '}', '~', '^', '/', '\\', '!', '&', '|',
+    ]) || query
+        .split_whitespace()
+        .any(|word| word == "NOT" || word.starts_with(['+', '-']))
+    {
+        return Err(format!("unimplemented query_string syntax: {}", query));
+    }
+    let mut words = Vec::new();
+    let mut operators = Vec::new();
+    let mut after_term = false;
+    for word in query.split_whitespace() {
+        match word {
+            "AND" | "OR" if after_term => {
+                operators.push(word == "AND");
+                after_term = false;
+            }
+            "AND" | "OR" => {
+                return Err(format!(
+                    "unimplemented query_string syntax - misplaced {}: {}",
+                    word, query
+                ))
+            }
+            _ => {
+                if after_term {
+                    operators.push(default_and);
+                }
+                words.push(word);
+                after_term = true;
+            }
+        }
+    }
+    if !after_term && !operators.is_empty() {
+        return Err(format!(
+            "unimplemented query_string syntax - trailing operator: {}",
+            query
+        ));
+    }
+    // Lucene gives AND precedence over OR, only the same operator throughout is the same here
+    let all_and = !operators.is_empty() && operators.iter().all(|and| *and);
+    if !all_and && operators.iter().any(|and| *and) {
+        return Err(format!(
+            "unimplemented query_string syntax - mixed AND and OR: {}",
+            query
+        ));
+    }
+    if all_and {
+        // Each word has to match, the filters of a clause all have to
+        Ok(words
+            .into_iter()
+            .map(|word| Filter::MultiMatch {
+                terms: tokenize(word),
+                fields: Vec::new(),
+            })
+            .collect())
+    } else {
+        Ok(vec![Filter::MultiMatch {
+            terms: words.into_iter().flat_map(tokenize).collect(),
+            fields: Vec::new(),
+        }])
+    }
+}
+
+// Parse a wildcard or prefix query like {"Description": "fi*"} or
+// {"Description": {"value": "fi*", "case_insensitive": true}} into the field, the pattern
+// and whether it ignores case, which it does unless `case_insensitive` is false
+fn parse_pattern_query(
+    kind: &str,
+    value: &Value,
+    query: &Value,
+) -> Result<(String, String, bool), String> {
+    let Some((field, pattern_value)) = query.as_object().and_then(single_field) else {
+        return Err(format!(
+            "unimplemented {} value - expected single field: {}",
+            kind, value
+        ));
+    };
+    let mut pattern = None;
+    let mut case_insensitive = true;
+    match pattern_value {
+        Value::String(shorthand) => pattern = Some(shorthand),
+        Object(params) => {
+            for (param_key, param_value) in params {
+                match (param_key.as_str(), param_value) {
+                    ("value", Value::String(param_value)) => pattern = Some(param_value),
+                    // The wildcard query also takes its pattern under its own name
+                    ("wildcard", Value::String(param_value)) if kind == "wildcard" => {
+                        pattern = Some(param_value)
+                    }
+                    ("case_insensitive", Bool(param_value)) => case_insensitive = *param_value,
+                    _ => return Err(format!("unimplemented {} parameter: {}", kind, param_key)),
+                }
+            }
+        }
+        _ => return Err(format!("unimplemented {} value: {}", kind, pattern_value)),
+    }
+    let Some(pattern) = pattern else {
+        return Err(format!(
+            "unimplemented {} value - missing value: {}",
+            kind, value
+        ));
+    };
+    // Escapes aren't supported, `\*` is a literal star in OpenSearch
+    if kind == "wildcard" && pattern.contains('\\') {
+        return Err(format!("unimplemented wildcard value: {}", pattern));
+    }
+    Ok((field.clone(), pattern.clone(), case_insensitive))
+}
+
+/// The only (field, value) pair of the object, like in {"location": {...}}
+fn single_field(object: &serde_json::Map<String, Value>) -> Option<(&String, &Value)> {
+    if object.len() != 1 {
+        return None;
+    }
+    object.iter().next()
+}
+
+// Parse multi_match fields like ["Title^2", "Description"], an empty array or "*" meaning
+// all the fields. Boosts are accepted but ignored for now, all the fields score alike.
+fn parse_multi_match_fields(value: &Value) -> Result<Vec<String>, String> {
+    let Array(values) = value else {
+        return Err(format!("unimplemented multi_match fields value: {}", value));
+    };
+    let mut fields = Vec::new();
+    for field in values {
+        let Value::String(field) = field else {
+            return Err(format!("unimplemented multi_match fields value: {}", value));
+        };
+        let field = match field.split_once('^') {
+            Some((field, boost)) if boost.parse::<f64>().is_ok() => field,
+            Some(_) => return Err(format!("unimplemented multi_match field boost: {}", field)),
+            None => field,
+        };
+        if field == "*" {
+            return Ok(Vec::new());
+        }
+        if field.contains('*') {
+            return Err(format!(
+                "unimplemented multi_match field pattern: {}",
+                field
+            ));
+        }
+        fields.push(field.to_string());
+    }
+    Ok(fields)
+}
+
+// Parse `track_total_hits` like true, false or 1000
+fn parse_track_total_hits(value: &str) -> Result<TrackTotalHits, String> {
+    match value {
+        "true" | "-1" => Ok(TrackTotalHits::Exact),
+        "false" => Ok(TrackTotalHits::Disabled),
+        _ => value
+            .parse()
+            .map(TrackTotalHits::UpTo)
+            .map_err(|_| format!("unimplemented track_total_hits value: {}", value)),
+    }
+}
+
+// Parse non-negative integer like "size": 500
+fn parse_pagination_value(key: &str, value: &Value) -> Result<usize, String> {
+    value
+        .as_u64()
+        .map(|value| value as usize)
+        .ok_or_else(|| format!("unimplemented {} value: {}", key, value))
+}
+
+/// Hoist clauses of trivially nested bool queries into their parent, e.g.
+/// `{"bool": {"filter": [{"bool": {"filter": [X]}}]}}` becomes `{"bool": {"filter": [X]}}`.
+///
+/// This is only done where it doesn't change matching or scoring: for a nested bool
+/// inside `filter` or `must` which has nothing but (non-empty) `filter`/`must` clauses.
+/// Inside `filter`, its clauses are hoisted as filter ones, unscored either way. Inside
+/// `must`, they keep their kind: nested must clauses add to the score like hoisted ones,
+/// see `BoolQuery::scoring_filters`.
+fn flatten_bool(query: &Value) -> Value {
+    let Some(Object(bool_query)) = query.get("bool") else {
+        return query.clone();
+    };
+    if query.as_object().map_or(0, |query| query.len()) != 1 {
+        return query.clone();
+    }
+
+    let mut flattened = bool_query.clone();
+    let mut hoisted: Vec<(&str, Value)> = Vec::new();
+    for (clause, clause_value) in bool_query {
+        let Array(items) = clause_value else {
+            continue;
+        };
+        let mut kept = Vec::new();
+        for item in items {
+            let item = flatten_bool(item);
+            match (clause.as_str(), hoistable_clauses(&item)) {
+                ("filter", Some(inner)) => {
+                    // Nothing is scored in filter context, however deep
+                    hoisted.extend(inner.into_iter().map(|(_, inner)| ("filter", inner)));
+                }
+                ("must", Some(inner)) => hoisted.extend(inner),
+                _ => kept.push(item),
+            }
+        }
+        flattened.insert(clause.clone(), Array(kept));
+    }
+    for (clause, item) in hoisted {
+        match flattened.get_mut(clause) {
+            Some(Array(items)) => items.push(item),
+            _ => {
+                flattened.insert(clause.to_string(), Array(vec![item]));
+            }
+        }
+    }
+    json!({ "bool": flattened })
+}
+
+/// Clauses of a bool query made only of `filter`/`must` clauses, which can be
+/// merged into the parent bool. None if merging could change the semantics.
+fn hoistable_clauses(query: &Value) -> Option<Vec<(&'static str, Value)>> {
+    let Object(query) = query else {
+        return None;
+    };
+    let Some(Object(bool_query)) = query.get("bool") else {
+        return None;
+    };
+    if query.len() != 1 {
+        return None;
+    }
+
+    let mut clauses = Vec::new();
+    for (clause, clause_value) in bool_query {
+        let Array(items) = clause_value else {
+            return None;
+        };
+        let clause = match clause.as_str() {
+            "filter" => "filter",
+            "must" => "must",
+            "should" | "must_not" if items.is_empty() => continue,
+            // should/must_not/minimum_should_match/boost/... change the semantics
+            _ => return None,
+        };
+        clauses.extend(items.iter().map(|item| (clause, item.clone())));
+    }
+    // Empty bool matches everything with a constant score, keep it as it is
+    if clauses.is_empty() {
+        return None;
+    }
+    Some(clauses)
+}
+
+// Parse `_source` like `false` or `{"includes": ["Description"], "excludes": []}`
+fn parse_source(value: &Value, parsed: &mut ParsedSearchRequest) -> Result<(), String> {
+    let source = match value {
+        Bool(enabled) => {
+            parsed.source_disabled = !enabled;
+            return Ok(());
+        }
+        Object(source) => source,
+        _ => return Err(format!("unimplemented _source value: {}", value)),
+    };
+    for (key, fields) in source {
+        let target = match key.as_str() {
+            "includes" => &mut parsed.source_includes,
+            "excludes" => &mut parsed.source_excludes,
+            _ => return Err(format!("unimplemented _source parameter: {}", key)),
+        };
+        let Array(fields) = fields else {
+            return Err(format!("unimplemented _source {} value: {}", key, fields));
+        };
+        for field in fields {
+            let Some(field) = field.as_str() else {
+                return Err(format!("unimplemented _source {} value: {}", key, field));
+            };
+            target.push(field.to_string());
+        }
+    }
+    Ok(())
+}
+
+fn parse_body(body: &Value, parsed: &mut ParsedSearchRequest) -> Result<(), String> {
+    let Object(map) = body else {
+        return Err(format!(
+            "expected JSON object in search body but got {}",
+            body
+        ));
+    };
+    for (key, value) in map {
+        match key.as_str() {
+            "_source" => parse_source(value, parsed)?,
+            "docvalue_fields" => {
+                if *value != Array(Vec::new()) {
+                    return Err(format!("unimplemented docvalue_fields value: {}", value));
+                }
+            }
+            "highlight" => {
+                parsed.highlight = Some(parse_highlight(value)?);
+            }
+            "query" => {
+                // Expect query to be a bare filter like {"match_all": {}} or:
+                //     "bool": Object {
+                //        "filter": Array [
+                //            Object {
+                //                "match_all": Object {},
+                //            },
+                //        ],
+                //        "must": Array [],
+                //        "must_not": Array [],
+                //        "should": Array [],
+                //    }
+
+                let value = &flatten_bool(value);
+                let Object(query) = value else {
+                    return Err(format!(
+                        "unimplemented query value - expected JSON object: {}",
+                        value
+                    ));
+                };
+                let query_keys: Vec<_> = query.keys().collect();
+                if query_keys != vec!["bool"] {
+                    // Simple queries often come without the bool wrapper, e.g. {"match_all": {}},
+                    // which is equivalent to a single-element must
+                    parse_filter(value, query, &mut parsed.query.must, 0)?;
+                    continue;
+                }
+
+                parse_bool_query(value, &mut parsed.query, 1)?;
+            }
+            "rescore" => {
+                parsed.rescore = Some(parse_rescore(value)?);
+            }
+            "track_total_hits" => {
+                parsed.track_total_hits = match value {
+                    Bool(_) | Value::Number(_) => parse_track_total_hits(&value.to_string())?,
+                    _ => return Err(format!("unimplemented track_total_hits value: {}", value)),
+                };
+            }
+            "aggs" | "aggregations" => {
+                parsed.aggregations = parse_aggregations(value)?;
+            }
+            "script_fields" => {
+                if *value != Object(serde_json::Map::new()) {
+                    return Err(format!("unimplemented script_fields value: {}", value));
+                }
+            }
+            "from" => {
+                parsed.from = Some(parse_pagination_value("from", value)?);
+            }
+            "size" => {
+                parsed.size = Some(parse_pagination_value("size", value)?);
+            }
+            "sort" => {
+                parsed.sort = parse_sort(value)?;
+            }
+            "search_after" => {
+                let Array(search_after) = value else {
+                    return Err(format!("unimplemented search_after value: {}", value));
+                };
+                parsed.search_after = Some(search_after.clone());
+            }
+            "stored_fields" => {
+                if *value != Array(vec![Value::String("*".to_string())]) {
+                    return Err(format!("unimplemented stored_fields value: {}", value));
+                }
+            }
+            "version" => {
+                let Bool(version) = value else {
+                    return Err(format!("unimplemented version value: {}", value));
+                };
+                parsed.version = *version;
+            }
+            _ => {
+                return Err(format!("unimplemented search parameter: {}", key));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Parse a bool query like {"bool": {"filter": [...], "should": [...]}} into `query`,
+// `depth` being 1 for the top-level one, more when nested in other bool queries
+fn parse_bool_query(value: &Value, query: &mut BoolQuery, depth: usize) -> Result<(), String> {
+    let Some(Object(bool_query)) = value.get("bool") else {
+        return Err(format!("unimplemented query value: {}", value));
+    };
+
+    let mut required_clauses = 0;
+    let mut minimum_should_match = None;
+    for (clause, clause_value) in bool_query {
+        match clause.as_str() {
+            "filter" | "must" | "must_not" | "should" => {}
+            "minimum_should_match" => {
+                minimum_should_match = Some(parse_minimum_should_match(clause_value)?);
+                continue;
+            }
+            _ => return Err(format!("unimplemented bool parameter: {}", clause)),
+        }
+        // A single clause can be given without the array
+        let items = match clause_value {
+            Array(items) => items.as_slice(),
+            Object(_) => std::slice::from_ref(clause_value),
+            _ => {
+                return Err(format!(
+                    "unimplemented query value - expected '{}' to be a JSON array: {}",
+                    clause, value
+                ))
+            }
+        };
+        for item in items {
+            let Object(item_map) = item else {
+                return Err(format!(
+                    "unimplemented query value - expected elements of '{}' to be JSON objects: {}",
+                    clause, value
+                ));
+            };
+            if clause == "filter" || clause == "must" {
+                // All the filter and must clauses have to match, only the must ones are scored
+                required_clauses += 1;
+                let filters = if clause == "must" {
+                    &mut query.must
+                } else {
+                    &mut query.filters
+                };
+                parse_filter(item, item_map, filters, depth)?;
+                continue;
+            }
+            // Each must_not/should clause on its own, its filters all have
+            // to match (none for match_all)
+            let mut clause_filters = Vec::new();
+            parse_filter(item, item_map, &mut clause_filters, depth)?;
+            if clause == "must_not" {
+                query.must_not.push(clause_filters);
+            } else {
+                query.should.push(clause_filters);
+            }
+        }
+    }
+    // Like in OpenSearch, should clauses are optional next to filter/must ones
+    query.minimum_should_match =
+        minimum_should_match.unwrap_or(if required_clauses == 0 && !query.should.is_empty() {
+            1
+        } else {
+            0
+        });
+    Ok(())
+}
+
+// Parse `minimum_should_match` of a bool query, only plain numbers like 2 or "2"
+fn parse_minimum_should_match(value: &Value) -> Result<usize, String> {
+    let minimum = match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    };
+    minimum
+        .map(|minimum| minimum as usize)
+        .ok_or_else(|| format!("unimplemented minimum_should_match value: {}", value))
+}
+
+// Parse rescore phase like
+//     {"window_size": 50, "query": {"rescore_query": {...}, "query_weight": 0.7, "rescore_query_weight": 1.2}}
+fn parse_rescore(value: &Value) -> Result<Rescore, String> {
+    // Rescore can be given as a single object or as a list of phases
+    let rescore = match value {
+        Array(rescores) if rescores.len() == 1 => &rescores[0],
+        Array(_) => {
+            return Err(format!(
+                "unimplemented rescore value - expected exactly one phase: {}",
+                value
+            ))
+        }
+        _ => value,
+    };
+    let Object(rescore) = rescore else {
+        return Err(format!("unimplemented rescore value: {}", value));
+    };
+
+    let mut parsed = Rescore {
+        window_size: 10,
+        query: Vec::new(),
+        query_weight: 1.0,
+        rescore_query_weight: 1.0,
+    };
+    for (key, rescore_value) in rescore {
+        match key.as_str() {
+            "window_size" => {
+                let Some(window_size) = rescore_value.as_u64() else {
+                    return Err(format!(
+                        "unimplemented rescore window_size: {}",
+                        rescore_value
+                    ));
+                };
+                parsed.window_size = window_size as usize;
+            }
+            "query" => {
+                let Object(query) = rescore_value else {
+                    return Err(format!("unimplemented rescore query: {}", rescore_value));
+                };
+                for (query_key, query_value) in query {
+                    match query_key.as_str() {
+                        "rescore_query" => {
+                            let Object(filter) = query_value else {
+                                return Err(format!(
+                                    "unimplemented rescore_query value: {}",
+                                    query_value
+                                ));
+                            };
+                            let mut rescore_query = Vec::new();
+                            parse_filter(query_value, filter, &mut rescore_query, 0)?;
+                            // The other clauses would all get the same constant score,
+                            // unlike in OpenSearch which scores term queries for instance
+                            if rescore_query
+                                .iter()
+                                .any(|filter| filter.text_query().is_none())
+                            {
+                                return Err(format!(
+                                    "unimplemented rescore_query - only match_all and text queries are scored: {}",
+                                    query_value
+                                ));
+                            }
+                            parsed.query = rescore_query;
+                        }
+                        "query_weight" => {
+                            let Some(weight) = query_value.as_f64() else {
+                                return Err(format!("unimplemented query_weight: {}", query_value));
+                            };
+                            parsed.query_weight = weight;
+                        }
+                        "rescore_query_weight" => {
+                            let Some(weight) = query_value.as_f64() else {
+                                return Err(format!(
+                                    "unimplemented rescore_query_weight: {}",
+                                    query_value
+                                ));
+                            };
+                            parsed.rescore_query_weight = weight;
+                        }
+                        "score_mode" => {
+                            if *query_value != "total" {
+                                return Err(format!(
+                                    "unimplemented rescore score_mode: {}",
+                                    query_value
+                                ));
+                            }
+                        }
+                        _ => {
+                            return Err(format!(
+                                "unimplemented rescore query parameter: {}",
+                                query_key
+                            ));
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(format!("unimplemented rescore parameter: {}", key));
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// Parse sort like `["Description"]` or `[{"Description": {"order": "desc"}}]`
+fn parse_sort(value: &Value) -> Result<Vec<(String, bool)>, String> {
+    let Array(sort) = value else {
+        return Err(format!("unimplemented sort value: {}", value));
+    };
+    let mut parsed = Vec::new();
+    for key in sort {
+        let (field, ascending) = match key {
+            // `_score` defaults to descending order, the fields to ascending
+            Value::String(field) => (field.clone(), field != "_score"),
+            Object(key) if key.len() == 1 => {
+                let (field, order) = key.iter().next().unwrap();
+                let direction = match order {
+                    Object(options) if options.keys().all(|option| option == "order") => {
+                        options.get("order").map_or(Some("asc"), Value::as_str)
+                    }
+                    _ => order.as_str(),
+                };
+                match direction {
+                    Some("asc") => (field.clone(), true),
+                    Some("desc") => (field.clone(), false),
+                    _ => return Err(format!("unimplemented sort order: {}", order)),
+                }
+            }
+            _ => return Err(format!("unimplemented sort value: {}", key)),
+        };
+        match field.as_str() {
+            // Insertion order breaks all ties, so the later keys don't matter
+            "_doc" if ascending => break,
+            "_doc" => return Err(format!("unimplemented sort value: {}", key)),
+            _ => parsed.push((field, ascending)),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Values of a hit for the sort keys, `_score` being its score and null a missing field.
+/// Reported in the hits, for clients to pass the last ones as `search_after`.
+fn sort_values((source, hit_score): &(&Value, f64), sort: &[(String, bool)]) -> Vec<Value> {
+    sort.iter()
+        .map(|(field, _)| match field.as_str() {
+            "_score" => json!(hit_score),
+            _ => source.get(field).cloned().unwrap_or(Value::Null),
+        })
+        .collect()
+}
+
+/// Order of two hits by their sort values. Documents missing the field (null)
+/// sort last in both directions, like OpenSearch does by default.
+fn compare_sort_values(a: &[Value], b: &[Value], sort: &[(String, bool)]) -> Ordering {
+    for ((a, b), (_, ascending)) in a.iter().zip(b).zip(sort) {
+        let ordering = match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            // Missing values aren't affected by the direction
+            (Value::Null, _) => return Ordering::Greater,
+            (_, Value::Null) => return Ordering::Less,
+            (a, b) => compare_values(a, b),
+        };
+        let ordering = if *ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Order of two field values: numbers numerically, strings lexicographically
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or(0.0)
+            .total_cmp(&b.as_f64().unwrap_or(0.0)),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Bool(a), Bool(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+/// Re-score the top `window_size` hits with the rescore query and re-sort just that window.
+/// Hits the rescore query doesn't match only get their weighted original score.
+fn apply_rescore(
+    corpus: &[Value],
+    result: &mut [(&Value, f64)],
+    rescore: &Rescore,
+    case_sensitive: bool,
+) {
+    let scorer = Scorer::new(corpus, &rescore.query, case_sensitive);
+    let window_size = rescore.window_size.min(result.len());
+    let window = &mut result[..window_size];
+    for (source, hit_score) in window.iter_mut() {
+        *hit_score *= rescore.query_weight;
+        if rescore
+            .query
+            .iter()
+            .all(|filter| filter.matches(source, case_sensitive, false))
+        {
+            *hit_score += scorer.score(source) * rescore.rescore_query_weight;
+        }
+    }
+    window.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+}
+
+/// Keep only the requested top-level fields of the document source.
+/// Empty `includes` means all fields, `excludes` are always removed.
+pub(crate) fn project_source(source: &Value, includes: &[String], excludes: &[String]) -> Value {
+    let Object(source) = source else {
+        return source.clone();
+    };
+    let projected = source
+        .iter()
+        .filter(|(field, _)| includes.is_empty() || includes.contains(field))
+        .filter(|(field, _)| !excludes.contains(field))
+        // Metadata of the document, reported next to the source
+        .filter(|(field, _)| !METADATA_FIELDS.contains(&field.as_str()))
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect();
+    Object(projected)
+}
+
+/// Split URL query string into options like ["timeout", "30000ms"], or just ["typed_keys"]
+/// for the ones without a value. Keys and values are percent-decoded, `+` meaning a space.
+pub(crate) fn split_options(query: &str) -> Vec<Vec<String>> {
+    query
+        .split('&')
+        .filter(|elem| !elem.is_empty())
+        .map(|elem| match elem.split_once('=') {
+            Some((key, value)) => vec![decode_option(key), decode_option(value)],
+            None => vec![decode_option(elem)],
+        })
+        .collect()
+}
+
+fn decode_option(encoded: &str) -> String {
+    percent_decode_str(&encoded.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Documents of the store matching the parsed request (with their scores)
+/// and whether the scanning stopped early because of the timeout.
+/// With an index, only the candidates of the text clauses are scanned.
+fn find_matches<'a>(
+    store: &'a DocumentStore,
+    parsed_request: &ParsedSearchRequest,
+    settings: &SearchSettings,
+    start: Instant,
+) -> (Vec<(&'a Value, f64)>, bool) {
+    let corpus = store.documents();
+    let candidates = store
+        .index()
+        .and_then(|index| parsed_request.candidates(index));
+    let documents: Box<dyn Iterator<Item = &Value>> = match &candidates {
+        Some(ids) => Box::new(ids.iter().map(|id| &corpus[*id])),
+        None => Box::new(corpus.iter()),
+    };
+
+    let timeout = parsed_request
+        .timeout
+        .map_or(settings.max_timeout, |timeout| {
+            timeout.min(settings.max_timeout)
+        });
+    let deadline = start + timeout;
+    let mut timed_out = false;
+
+    let mut result = Vec::new();
+    for (position, source) in documents.enumerate() {
+        if position % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+            timed_out = true;
+            break;
+        }
+        if parsed_request.matches(source, settings.case_sensitive) {
+            result.push((source, 0.0));
+        }
+    }
+    (result, timed_out)
+}
+
+/// Try to handle a _search request against the documents of the store. If we can
+/// handle it, return the response JSON, else return an error.
+///
+/// Scanning stops at the request `timeout` (capped by `max_timeout`), returning
+/// the hits found so far with `timed_out: true`, like OpenSearch does.
+pub fn search(
+    store: &DocumentStore,
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<Value, SearchError> {
+    let start = Instant::now();
+    let parsed_request = parse_search(input, settings)?;
+
+    let (mut result, timed_out) = find_matches(store, &parsed_request, settings, start);
+    let corpus = store.documents();
+
+    // Matching should clauses add to the score
+    let scorer = Scorer::new(
+        corpus,
+        parsed_request.query.scoring_filters(),
+        settings.case_sensitive,
+    );
+    for (source, hit_score) in result.iter_mut() {
+        *hit_score = scorer.score(source);
+    }
+    // Stable sort, so ties keep the insertion order
+    result.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    if let Some(rescore) = &parsed_request.rescore {
+        apply_rescore(corpus, &mut result, rescore, settings.case_sensitive);
+    }
+    // Position of the first hit after the `search_after` cursor, the hits before aren't paged
+    let mut after = 0;
+    if !parsed_request.sort.is_empty() {
+        let sort = &parsed_request.sort;
+        // Sort values are computed once per hit, not on each comparison
+        let mut sorted: Vec<_> = result
+            .into_iter()
+            .map(|hit| (sort_values(&hit, sort), hit))
+            .collect();
+        sorted.sort_by(|(a, _), (b, _)| compare_sort_values(a, b, sort));
+        if let Some(search_after) = &parsed_request.search_after {
+            after = sorted.partition_point(|(values, _)| {
+                compare_sort_values(values, search_after, sort) != Ordering::Greater
+            });
+        }
+        result = sorted.into_iter().map(|(_, hit)| hit).collect();
+    }
+    let max_score = result
+        .iter()
+        .map(|(_, hit_score)| *hit_score)
+        .fold(0.0, f64::max);
+
+    // Total is reported before pagination, so clients can compute the number of pages.
+    // Like the aggregations, it's the one of the whole query whatever the cursor.
+    let total = result.len();
+    let page: Vec<_> = result[after..]
+        .iter()
+        .skip(parsed_request.from.unwrap_or(0))
+        .take(parsed_request.size.unwrap_or(settings.default_size))
+        .collect();
+    let aggregations: serde_json::Map<_, _> = parsed_request
+        .aggregations
+        .iter()
+        .map(|(name, aggregation)| {
+            let key = if parsed_request.typed_keys {
+                format!("{}#{}", aggregation.type_name(), name)
+            } else {
+                name.clone()
+            };
+            (
+                key,
+                aggregation.compute(result.iter().map(|(source, _)| *source)),
+            )
+        })
+        .collect();
+
+    let mut hits_total = parsed_request.track_total_hits.total(total, page.len());
+    if parsed_request.rest_total_hits_as_int {
+        hits_total = hits_total["value"].take();
+    }
+
+    let mut response = json!({
+        "timed_out": timed_out,
+        "_shards": {
+            "total": 1,
+            "successful": 1,
+            "skipped": 0,
+            "failed": 0,
+        },
+        "hits": {
+            "total": hits_total,
+            "max_score": max_score,
+            "hits": page.iter().map(|(r, hit_score)| hit(r, *hit_score, &parsed_request, settings.case_sensitive)).collect::<Vec<_>>()
+        }
+    });
+    if !aggregations.is_empty() {
+        response["aggregations"] = Object(aggregations);
+    }
+    // Once the whole response is built
+    response["took"] = json!(took(start, settings));
+    Ok(response)
+}
+
+/// Milliseconds spent on the request since `start`, unless the settings fix the value
+fn took(start: Instant, settings: &SearchSettings) -> u64 {
+    settings
+        .fixed_took
+        .unwrap_or_else(|| u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Try to handle a _msearch request: newline-delimited pairs of a header and a search body.
+/// All the searches have to be supported, otherwise the whole request is rejected.
+/// URL options of the _msearch apply to each of the searches.
+pub fn msearch(
+    store: &DocumentStore,
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<Value, SearchError> {
+    let start = Instant::now();
+    let mut responses = Vec::new();
+    for (index, body) in msearch_searches(input)? {
+        let sub_input = SearchInput {
+            // Indices of the header take precedence over the ones of the path
+            index: index.as_deref().or(input.index),
+            query: input.query,
+            body,
+        };
+        let mut response = search(store, &sub_input, settings)?;
+        response["status"] = json!(200);
+        responses.push(response);
+    }
+    Ok(json!({
+        "took": took(start, settings),
+        "responses": responses,
+    }))
+}
+
+/// Index expression of its header and body of one of the searches of a _msearch request
+type MsearchSearch<'a> = (Option<String>, &'a [u8]);
+
+/// Searches of a _msearch request, in order
+fn msearch_searches<'a>(input: &SearchInput<'a>) -> Result<Vec<MsearchSearch<'a>>, SearchError> {
+    let lines: Vec<_> = input
+        .body
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .collect();
+    if lines.len() % 2 != 0 {
+        return Err("expected header and body pairs in _msearch request"
+            .to_string()
+            .into());
+    }
+    lines
+        .chunks(2)
+        .map(|pair| {
+            let header: Value = serde_json::from_slice(pair[0]).map_err(SearchError::Malformed)?;
+            Ok((parse_msearch_header(&header)?, pair[1]))
+        })
+        .collect()
+}
+
+/// Check the _msearch header is supported, returning its index expression if any
+fn parse_msearch_header(header: &Value) -> Result<Option<String>, String> {
+    let Object(header) = header else {
+        return Err(format!("unimplemented _msearch header: {}", header));
+    };
+    let mut index = None;
+    for (key, value) in header {
+        match key.as_str() {
+            "index" => {
+                let expression = match value {
+                    Value::String(expression) => Some(expression.clone()),
+                    Array(indices) => indices
+                        .iter()
+                        .map(|index| index.as_str())
+                        .collect::<Option<Vec<_>>>()
+                        .map(|indices| indices.join(",")),
+                    _ => None,
+                };
+                let Some(expression) = expression else {
+                    return Err(format!("unimplemented _msearch index value: {}", value));
+                };
+                index = Some(expression);
+            }
+            "preference" => {}
+            "ignore_unavailable" => {
+                if *value != Bool(true) {
+                    return Err(format!(
+                        "unimplemented _msearch ignore_unavailable value: {}",
+                        value
+                    ));
+                }
+            }
+            _ => return Err(format!("unimplemented _msearch header parameter: {}", key)),
+        }
+    }
+    Ok(index)
+}
+
+/// Search hit for the document, with the source projected and the terms highlighted as requested,
+/// and its version if asked for.
+/// The metadata fields of the document (`_index`, `_id`, `_version`) aren't part of the source.
+fn hit(
+    source: &Value,
+    hit_score: f64,
+    parsed_request: &ParsedSearchRequest,
+    case_sensitive: bool,
+) -> Value {
+    let mut hit = json!({
+        "_index": document_index(source),
+        "_id": document_id(source),
+        "_score": hit_score,
+    });
+    if parsed_request.version {
+        hit["_version"] = json!(document_version(source));
+    }
+    if !parsed_request.source_disabled {
+        hit["_source"] = project_source(
+            source,
+            &parsed_request.source_includes,
+            &parsed_request.source_excludes,
+        );
+    }
+    if !parsed_request.sort.is_empty() {
+        hit["sort"] = json!(sort_values(&(source, hit_score), &parsed_request.sort));
+    }
+    if let Some(highlight) = &parsed_request.highlight {
+        let filters = parsed_request.query.highlighted_filters();
+        if let Some(highlight) = highlight.highlight(source, filters, case_sensitive) {
+            hit["highlight"] = highlight;
+        }
+    }
+    hit
+}
+
+/// Try to handle a _count request against the documents of the store: with a `query`
+/// in the body, a `q` URL option, or no query at all.
+pub fn count(
+    store: &DocumentStore,
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<Value, SearchError> {
+    let start = Instant::now();
+    let parsed_request = parse_count(input, settings)?;
+
+    let (result, timed_out) = find_matches(store, &parsed_request, settings, start);
+    // Unlike partial hits flagged `timed_out`, a partial count would pass for the exact one
+    if timed_out {
+        return Err(
+            "unimplemented count - timed out before counting all the documents"
+                .to_string()
+                .into(),
+        );
+    }
+
+    Ok(json!({
+        "count": result.len(),
+        "_shards": {
+            "total": 1,
+            "successful": 1,
+            "skipped": 0,
+            "failed": 0,
+        },
+    }))
+}
+
+/// Parse and check a _search request
+fn parse_search(
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<ParsedSearchRequest, SearchError> {
+    let mut parsed_request = ParsedSearchRequest::default();
+
+    if let Some(index) = input.index {
+        parsed_request.indices = parse_indices(index)?;
+    }
+    parse_options(&split_options(input.query), &mut parsed_request)?;
+    let q = parsed_request.q.take();
+    if !input.body.iter().all(u8::is_ascii_whitespace) {
+        // Body takes precedence over the `q` URL option
+        let body: Value = serde_json::from_slice(input.body).map_err(SearchError::Malformed)?;
+        parse_body(&body, &mut parsed_request)?;
+    } else if let Some(q) = q {
+        parse_query_string(&q, &mut parsed_request)?;
+    }
+    parsed_request.resolve_aliases(&settings.field_aliases);
+
+    let sort_by_fields = parsed_request
+        .sort
+        .iter()
+        .any(|(field, _)| field != "_score");
+    if sort_by_fields && parsed_request.rescore.is_some() {
+        return Err("cannot use sort in conjunction with rescore"
+            .to_string()
+            .into());
+    }
+    if let Some(search_after) = &parsed_request.search_after {
+        if parsed_request.sort.is_empty() {
+            return Err("unimplemented search_after without sort".to_string().into());
+        }
+        // The `_doc` tiebreaker isn't kept in the sort keys, so it can't be matched either
+        if search_after.len() != parsed_request.sort.len() {
+            return Err(format!(
+                "unimplemented search_after value - expected {} sort values: {}",
+                parsed_request.sort.len(),
+                json!(search_after)
+            )
+            .into());
+        }
+        if parsed_request.from.unwrap_or(0) > 0 {
+            return Err("from must be 0 when search_after is used"
+                .to_string()
+                .into());
+        }
+    }
+    Ok(parsed_request)
+}
+
+/// Parse a _count request, only the query can be in its body
+fn parse_count(
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<ParsedSearchRequest, SearchError> {
+    let mut parsed_request = ParsedSearchRequest::default();
+
+    if let Some(index) = input.index {
+        parsed_request.indices = parse_indices(index)?;
+    }
+    parse_options(&split_options(input.query), &mut parsed_request)?;
+    let q = parsed_request.q.take();
+    if !input.body.iter().all(u8::is_ascii_whitespace) {
+        let body: Value = serde_json::from_slice(input.body).map_err(SearchError::Malformed)?;
+        // Unlike _search, the query is all there can be in the body
+        if let Some(key) = body
+            .as_object()
+            .and_then(|body| body.keys().find(|key| *key != "query"))
+        {
+            return Err(format!("unimplemented count parameter: {}", key).into());
+        }
+        parse_body(&body, &mut parsed_request)?;
+    } else if let Some(q) = q {
+        parse_query_string(&q, &mut parsed_request)?;
+    }
+    parsed_request.resolve_aliases(&settings.field_aliases);
+    Ok(parsed_request)
+}
+
+/// Canonical form of a _search request: the parsed request with its defaults filled in,
+/// the same for requests searching the same way whatever the order of their URL options
+/// or body keys, or the defaults they spell out. Keys the cached responses.
+pub fn normalized_search(
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<String, SearchError> {
+    Ok(parse_search(input, settings)?.normalized(settings))
+}
+
+/// Canonical form of a _count request, see `normalized_search`
+pub fn normalized_count(
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<String, SearchError> {
+    Ok(parse_count(input, settings)?.normalized(settings))
+}
+
+/// Canonical form of a _msearch request, one line per search, see `normalized_search`
+pub fn normalized_msearch(
+    input: &SearchInput,
+    settings: &SearchSettings,
+) -> Result<String, SearchError> {
+    let mut searches = Vec::new();
+    for (index, body) in msearch_searches(input)? {
+        let sub_input = SearchInput {
+            index: index.as_deref().or(input.index),
+            query: input.query,
+            body,
+        };
+        searches.push(normalized_search(&sub_input, settings)?);
+    }
+    Ok(searches.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoring::CONSTANT_SCORE;
+
+    type SearchEndpoint =
+        fn(&DocumentStore, &SearchInput, &SearchSettings) -> Result<Value, SearchError>;
+
+    /// Response of the endpoint to the request on the store, which has to be emulated
+    fn emulate(endpoint: SearchEndpoint, store: &DocumentStore, query: &str, body: Value) -> Value {
+        let body = if body.is_null() {
+            Vec::new()
+        } else {
+            body.to_string().into_bytes()
+        };
+        let input = SearchInput {
+            index: None,
+            query,
+            body: &body,
+        };
+        endpoint(store, &input, &SearchSettings::default()).unwrap()
+    }
+
+    fn search_lyrics(body: Value) -> Value {
+        emulate(search, &DocumentStore::default(), "", body)
+    }
+
+    /// Why the search isn't emulated
+    fn unsupported(body: Value) -> String {
+        let body = body.to_string();
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: body.as_bytes(),
+        };
+        match search(
+            &DocumentStore::default(),
+            &input,
+            &SearchSettings::default(),
+        ) {
+            Err(SearchError::Unsupported(reason)) => reason,
+            other => panic!("expected unsupported search, got {:?}", other),
+        }
+    }
+
+    /// The lyrics, each in the index in turn
+    fn lyrics_in(indices: &[&str]) -> DocumentStore {
+        DocumentStore::new(
+            DocumentStore::default()
+                .documents()
+                .iter()
+                .zip(indices)
+                .map(|(lyric, index)| {
+                    let mut lyric = lyric.clone();
+                    lyric["_index"] = json!(index);
+                    lyric
+                })
+                .collect(),
+        )
+    }
+
+    /// Description of the hits, in order
+    fn descriptions(response: &Value) -> Vec<&str> {
+        response["hits"]["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["_source"]["Description"].as_str().unwrap())
+            .collect()
+    }
+
+    fn scores(response: &Value) -> Vec<f64> {
+        response["hits"]["hits"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hit| hit["_score"].as_f64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rescore_only_scores_the_matching_hits() {
+        let response = search_lyrics(json!({
+            "query": {"match_all": {}},
+            "rescore": {
+                "window_size": 10,
+                "query": {
+                    "rescore_query": {"match": {"Description": "fire"}},
+                    "query_weight": 0.5,
+                    "rescore_query_weight": 5,
+                },
+            },
+        }));
+        let scores = scores(&response);
+        assert!(descriptions(&response)[0].starts_with("Through the fire"));
+        assert!(scores[0] > 0.5);
+        assert_eq!(scores[1..], [0.5, 0.5]);
+    }
+
+    #[test]
+    fn rescore_scores_phrases_as_phrases() {
+        let rescored = |phrase: &str| {
+            let response = search_lyrics(json!({
+                "query": {"match_all": {}},
+                "rescore": {
+                    "query": {"rescore_query": {"match_phrase": {"Description": phrase}}},
+                },
+            }));
+            scores(&response)[0]
+        };
+        // The terms are all there, but not as a phrase
+        assert_eq!(rescored("fire through"), 1.0);
+        assert!(rescored("through the fire") > 1.0);
+    }
+
+    #[test]
+    fn rescore_without_text_query_is_unsupported() {
+        let reason = unsupported(json!({
+            "query": {"match_all": {}},
+            "rescore": {
+                "query": {
+                    "rescore_query": {"term": {"Description": "nope"}},
+                    "rescore_query_weight": 5,
+                },
+            },
+        }));
+        assert!(reason.contains("rescore_query"), "{}", reason);
+    }
+
+    /// Which of the documents the bool query matches
+    fn bool_matches(query: &Value, documents: &[Value]) -> Vec<bool> {
+        let mut parsed = BoolQuery::default();
+        parse_bool_query(query, &mut parsed, 1).unwrap();
+        documents
+            .iter()
+            .map(|document| parsed.matches(document, false, false))
+            .collect()
+    }
+
+    fn bool_scores(query: &Value, documents: &[Value]) -> Vec<f64> {
+        let mut parsed = BoolQuery::default();
+        parse_bool_query(query, &mut parsed, 1).unwrap();
+        let scorer = Scorer::new(documents, parsed.scoring_filters(), false);
+        documents
+            .iter()
+            .map(|document| scorer.score(document))
+            .collect()
+    }
+
+    /// Flatten the query, checking that it matches and scores the same documents either way
+    fn flatten_checked(query: Value) -> Value {
+        let documents = [
+            json!({"Description": "fire", "color": "red", "size": 1}),
+            json!({"Description": "fire wall", "color": "blue", "size": 2}),
+            json!({"Description": "wall", "color": "red", "size": 3}),
+            json!({"Description": "limit", "color": "green", "size": 2}),
+            json!({}),
+        ];
+        let flattened = flatten_bool(&query);
+        assert_eq!(
+            bool_matches(&flattened, &documents),
+            bool_matches(&query, &documents),
+            "{} flattened into {}",
+            query,
+            flattened
+        );
+        let scores = bool_scores(&query, &documents);
+        let flattened_scores = bool_scores(&flattened, &documents);
+        // The same clauses, but not necessarily summed in the same order
+        assert!(
+            scores
+                .iter()
+                .zip(&flattened_scores)
+                .all(|(score, flattened_score)| (score - flattened_score).abs() < 1e-9),
+            "{} scored {:?}, flattened into {} {:?}",
+            query,
+            scores,
+            flattened,
+            flattened_scores
+        );
+        flattened
+    }
+
+    #[test]
+    fn flatten_bool_hoists_nested_filter_and_must() {
+        let flattened = flatten_checked(json!({"bool": {
+            "filter": [{"bool": {"must": [{"bool": {"filter": [{"term": {"color": "red"}}]}}]}}],
+            "must": [{"bool": {"must": [{"match": {"Description": "fire"}}], "should": []}}],
+        }}));
+        assert_eq!(
+            flattened,
+            json!({"bool": {
+                "filter": [{"term": {"color": "red"}}],
+                "must": [{"match": {"Description": "fire"}}],
+            }})
+        );
+    }
+
+    #[test]
+    fn flatten_bool_keeps_nested_should() {
+        let query = json!({"bool": {"must": [
+            {"bool": {
+                "should": [{"match": {"Description": "fire"}}, {"term": {"color": "green"}}],
+                "minimum_should_match": 1,
+            }},
+            {"bool": {"must": [
+                {"range": {"size": {"gte": 2}}},
+                {"bool": {"should": [{"term": {"color": "red"}}, {"term": {"color": "blue"}}]}},
+            ]}},
+        ]}});
+        let flattened = flatten_checked(query);
+        // Only the bool of must clauses is merged, the should ones stay nested
+        assert_eq!(flattened["bool"]["must"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn flatten_bool_keeps_minimum_should_match_of_nested_should() {
+        flatten_checked(json!({"bool": {"filter": [{"bool": {
+            "should": [
+                {"bool": {"filter": [{"match": {"Description": "fire"}}]}},
+                {"bool": {"filter": [{"match": {"Description": "wall"}}]}},
+            ],
+            "minimum_should_match": 2,
+        }}]}}));
+    }
+
+    #[test]
+    fn flatten_bool_keeps_must_not_of_bool() {
+        let query = json!({"bool": {"must_not": [{"bool": {"must": [
+            {"match": {"Description": "fire"}},
+            {"term": {"color": "red"}},
+        ]}}]}});
+        // Not hoisted: must_not of both isn't must_not of each
+        assert_eq!(flatten_checked(query.clone()), query);
+        flatten_checked(json!({"bool": {"filter": [{"bool": {"must_not": [
+            {"bool": {"filter": [{"bool": {"filter": [{"term": {"size": 2}}]}}]}},
+        ]}}]}}));
+    }
+
+    #[test]
+    fn count_is_the_hits_total_of_the_search() {
+        let query = json!({"query": {"multi_match": {"query": "through fire"}}});
+        let store = DocumentStore::default();
+        let counted = emulate(count, &store, "", query.clone());
+        let searched = emulate(search, &store, "", query);
+        assert_eq!(counted["count"], 2);
+        assert_eq!(counted["count"], searched["hits"]["total"]["value"]);
+    }
+
+    #[test]
+    fn count_timing_out_is_unsupported() {
+        let settings = SearchSettings {
+            max_timeout: Duration::ZERO,
+            ..SearchSettings::default()
+        };
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: b"",
+        };
+        let counted = count(&DocumentStore::default(), &input, &settings);
+        assert!(matches!(counted, Err(SearchError::Unsupported(_))));
+    }
+
+    #[test]
+    fn sort_lyrics_descending_by_description() {
+        let response = search_lyrics(json!({"sort": [{"Description": {"order": "desc"}}]}));
+        let descriptions = descriptions(&response);
+        assert!(descriptions[0].starts_with("You tell me"));
+        assert!(descriptions[1].starts_with("When it's this good"));
+        assert!(descriptions[2].starts_with("Through the fire"));
+        assert_eq!(
+            response["hits"]["hits"][0]["sort"],
+            json!([descriptions[0]])
+        );
+    }
+
+    #[test]
+    fn sort_lyrics_ascending_by_description() {
+        let response = search_lyrics(json!({"sort": ["Description"]}));
+        assert!(descriptions(&response)[0].starts_with("Through the fire"));
+    }
+
+    #[test]
+    fn multi_match_ignores_case() {
+        for query in ["FIRE", "Fire", "fire"] {
+            let response = search_lyrics(json!({"query": {"multi_match": {"query": query}}}));
+            let descriptions = descriptions(&response);
+            assert_eq!(descriptions.len(), 1, "{}", query);
+            assert!(descriptions[0].starts_with("Through the fire"), "{}", query);
+        }
+    }
+
+    #[test]
+    fn multi_match_is_exact_when_case_sensitive() {
+        let settings = SearchSettings {
+            case_sensitive: true,
+            ..SearchSettings::default()
+        };
+        let body = json!({"query": {"multi_match": {"query": "FIRE"}}}).to_string();
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: body.as_bytes(),
+        };
+        let response = search(&DocumentStore::default(), &input, &settings).unwrap();
+        assert!(descriptions(&response).is_empty());
+    }
+
+    #[test]
+    fn q_option_searches_without_a_body() {
+        let store = lyrics_in(&["x", "x", "x"]);
+        let input = SearchInput {
+            index: Some("x"),
+            query: "q=limit",
+            body: b"",
+        };
+        let response = search(&store, &input, &SearchSettings::default()).unwrap();
+        assert_eq!(response["hits"]["total"]["value"], 1);
+        assert!(descriptions(&response)[0].starts_with("Through the fire, to the limit"));
+
+        let response = emulate(search, &store, "q=Description:limit", Value::Null);
+        assert_eq!(response["hits"]["total"]["value"], 1);
+        let response = emulate(search, &store, "q=Title:limit", Value::Null);
+        assert_eq!(response["hits"]["total"]["value"], 0);
+    }
+
+    #[test]
+    fn body_takes_precedence_over_the_q_option() {
+        let response = emulate(
+            search,
+            &DocumentStore::default(),
+            "q=limit",
+            json!({"query": {"match_all": {}}}),
+        );
+        assert_eq!(response["hits"]["total"]["value"], 3);
+    }
+
+    #[test]
+    fn msearch_answers_each_search() {
+        let body = [
+            json!({}),
+            json!({"query": {"match_all": {}}}),
+            json!({"index": DEFAULT_INDEX}),
+            json!({"query": {"multi_match": {"query": "fire"}}}),
+        ]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: body.as_bytes(),
+        };
+        let response = msearch(
+            &DocumentStore::default(),
+            &input,
+            &SearchSettings::default(),
+        )
+        .unwrap();
+        let responses = response["responses"].as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["hits"]["total"]["value"], 3);
+        assert_eq!(responses[1]["hits"]["total"]["value"], 1);
+        assert!(descriptions(&responses[1])[0].starts_with("Through the fire"));
+    }
+
+    #[test]
+    fn msearch_with_an_unsupported_search_is_not_emulated() {
+        let body = "{}\n{\"query\": {\"match_all\": {}}}\n{}\n{\"query\": {\"fuzzy\": {}}}\n";
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: body.as_bytes(),
+        };
+        let response = msearch(
+            &DocumentStore::default(),
+            &input,
+            &SearchSettings::default(),
+        );
+        assert!(matches!(response, Err(SearchError::Unsupported(_))));
+    }
+
+    #[test]
+    fn options_are_url_decoded() {
+        let options = split_options("q=through%20the+fire&_source_includes=Desc%2CTitle&pretty");
+        assert_eq!(
+            options,
+            [
+                vec!["q".to_string(), "through the fire".to_string()],
+                vec!["_source_includes".to_string(), "Desc,Title".to_string()],
+                vec!["pretty".to_string()],
+            ]
+        );
+        assert_eq!(split_options("q=a%26b%3Dc%2B%25"), [["q", "a&b=c+%"]]);
+    }
+
+    #[test]
+    fn encoded_q_option_is_searched() {
+        let store = DocumentStore::default();
+        let response = emulate(
+            search,
+            &store,
+            "q=Description%3Athrough%20fire",
+            Value::Null,
+        );
+        assert_eq!(response["hits"]["total"]["value"], 2);
+    }
+
+    #[test]
+    fn match_phrase_matches_contiguous_terms() {
+        let phrase =
+            |query: &str| search_lyrics(json!({"query": {"match_phrase": {"Description": query}}}));
+        let response = phrase("through the FIRE");
+        assert_eq!(response["hits"]["total"]["value"], 1);
+        assert!(descriptions(&response)[0].starts_with("Through the fire"));
+        assert_eq!(phrase("fire through")["hits"]["total"]["value"], 0);
+        let slop =
+            json!({"query": {"match_phrase": {"Description": {"query": "fire", "slop": 1}}}});
+        assert!(unsupported(slop).contains("slop"));
+    }
+
+    #[test]
+    fn bool_filter_and_must_are_combined() {
+        let response = search_lyrics(json!({"query": {"bool": {
+            "filter": [{"match_all": {}}],
+            "must": [{"multi_match": {"query": "through"}}],
+        }}}));
+        assert_eq!(response["hits"]["total"]["value"], 2);
+        let response = search_lyrics(json!({"query": {"bool": {
+            "filter": [{"match_all": {}}],
+            "must": [{"multi_match": {"query": "through"}}],
+            "must_not": [{"multi_match": {"query": "fire"}}],
+        }}}));
+        assert_eq!(response["hits"]["total"]["value"], 1);
+        assert!(descriptions(&response)[0].starts_with("You tell me"));
+    }
+
+    #[test]
+    fn should_matches_are_optional_next_to_must() {
+        let response = search_lyrics(json!({"query": {"bool": {
+            "must": [{"match_all": {}}],
+            "should": [{"multi_match": {"query": "fire"}}],
+        }}}));
+        assert_eq!(response["hits"]["total"]["value"], 3);
+        let response = search_lyrics(json!({"query": {"bool": {
+            "should": [{"multi_match": {"query": "fire"}}, {"multi_match": {"query": "saying"}}],
+        }}}));
+        assert_eq!(response["hits"]["total"]["value"], 2);
+    }
+
+    #[test]
+    fn searches_only_the_indices_of_the_path() {
+        let store = lyrics_in(&["songs-1984", "songs-1985", "poems"]);
+        let total = |index| {
+            let input = SearchInput {
+                index: Some(index),
+                query: "",
+                body: b"",
+            };
+            search(&store, &input, &SearchSettings::default()).unwrap()["hits"]["total"]["value"]
+                .clone()
+        };
+        assert_eq!(total("songs-1984"), 1);
+        assert_eq!(total("songs-*"), 2);
+        assert_eq!(total("songs-1985,poems"), 2);
+        assert_eq!(total("other"), 0);
+    }
+
+    #[test]
+    fn track_total_hits_bounds_the_total() {
+        let total = |track_total_hits: Value| {
+            search_lyrics(json!({"size": 1, "track_total_hits": track_total_hits}))["hits"]["total"]
+                .clone()
+        };
+        assert_eq!(total(json!(true)), json!({"value": 3, "relation": "eq"}));
+        assert_eq!(total(json!(false)), json!({"value": 1, "relation": "gte"}));
+        assert_eq!(total(json!(2)), json!({"value": 2, "relation": "gte"}));
+        assert_eq!(total(json!(5)), json!({"value": 3, "relation": "eq"}));
+        let response = emulate(
+            search,
+            &DocumentStore::default(),
+            "track_total_hits=2",
+            Value::Null,
+        );
+        assert_eq!(
+            response["hits"]["total"],
+            json!({"value": 2, "relation": "gte"})
+        );
+    }
+
+    #[test]
+    fn search_after_pages_through_the_lyrics() {
+        let mut pages = Vec::new();
+        let mut search_after: Option<Value> = None;
+        loop {
+            let mut body = json!({
+                "size": 1,
+                "sort": [{"Description": "asc"}],
+                "aggs": {"lyrics": {"value_count": {"field": "Description"}}},
+            });
+            if let Some(search_after) = search_after {
+                body["search_after"] = search_after;
+            }
+            let response = search_lyrics(body);
+            // Those of the whole query on every page
+            assert_eq!(response["hits"]["total"]["value"], 3);
+            assert_eq!(response["aggregations"]["lyrics"]["value"], 3);
+            let Some(hit) = response["hits"]["hits"].as_array().unwrap().first() else {
+                break;
+            };
+            pages.push(hit["_source"]["Description"].as_str().unwrap().to_string());
+            search_after = Some(hit["sort"].clone());
+        }
+        let mut sorted = pages.clone();
+        sorted.sort();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages, sorted);
+        assert!(unsupported(json!({"search_after": ["a"]})).contains("search_after"));
+    }
+
+    #[test]
+    fn rest_total_hits_as_int_flattens_the_total() {
+        let store = DocumentStore::default();
+        let total = |query| emulate(search, &store, query, Value::Null)["hits"]["total"].clone();
+        assert_eq!(total(""), json!({"value": 3, "relation": "eq"}));
+        assert_eq!(
+            total("rest_total_hits_as_int=false"),
+            json!({"value": 3, "relation": "eq"})
+        );
+        assert_eq!(total("rest_total_hits_as_int=true"), json!(3));
+        assert_eq!(total("rest_total_hits_as_int"), json!(3));
+    }
+
+    #[test]
+    fn indexed_store_finds_the_same_hits_as_a_scan() {
+        let words = [
+            "fire", "limit", "wall", "chance", "risk", "smart", "start", "good",
+        ];
+        let documents: Vec<Value> = (0..5000)
+            .map(|i| {
+                json!({
+                    "Title": format!("{} {}", words[i % 8], words[i / 8 % 8]),
+                    "Description": format!("{} {} {}", words[i % 5], words[i % 7], words[i % 3]),
+                })
+            })
+            .collect();
+        let indexed = DocumentStore::new(documents.clone());
+        let scanned = DocumentStore::unindexed(documents);
+        assert!(indexed.index().is_some());
+        for query in [
+            json!({"multi_match": {"query": "fire wall"}}),
+            json!({"match": {"Title": "Chance"}}),
+            json!({"bool": {"must": [{"match": {"Description": "risk"}}, {"match": {"Title": "good"}}]}}),
+            json!({"bool": {"should": [{"match_phrase": {"Title": "smart start"}}, {"match_all": {}}]}}),
+        ] {
+            let body = json!({"query": query, "size": 10000});
+            let indexed = emulate(search, &indexed, "", body.clone());
+            let scanned = emulate(search, &scanned, "", body);
+            assert_eq!(indexed["hits"], scanned["hits"], "{}", query);
+        }
+    }
+
+    #[test]
+    fn lenient_term_matches_numbers_spelled_as_strings() {
+        let store = DocumentStore::default();
+        let body = json!({"query": {"term": {"_version": DOCUMENT_VERSION.to_string()}}});
+        let total =
+            |query| emulate(search, &store, query, body.clone())["hits"]["total"]["value"].clone();
+        assert_eq!(total("lenient=true"), 3);
+        assert_eq!(total(""), 0);
+    }
+
+    #[test]
+    fn nested_bool_queries_are_combined() {
+        let response = search_lyrics(json!({"query": {"bool": {
+            "filter": [{"bool": {
+                "should": [
+                    {"bool": {"must": [{"match": {"Description": "through"}}, {"match": {"Description": "limit"}}]}},
+                    {"match": {"Description": "saying"}},
+                ],
+            }}],
+        }}}));
+        let mut matched = descriptions(&response);
+        matched.sort();
+        assert_eq!(matched.len(), 2);
+        assert!(matched[0].starts_with("Through the fire"));
+        assert!(matched[1].starts_with("When it's this good"));
+    }
+
+    #[test]
+    fn too_deep_bool_queries_are_not_emulated() {
+        let mut query = json!({"match_all": {}});
+        // Should clauses can't be flattened into their parent
+        for _ in 0..20 {
+            query = json!({"bool": {"should": [query]}});
+        }
+        assert!(unsupported(json!({"query": query})).contains("deep"));
+    }
+
+    #[test]
+    fn scroll_searches_are_never_emulated() {
+        let input = SearchInput {
+            index: None,
+            query: "scroll=1m",
+            body: br#"{"query": {"match_all": {}}}"#,
+        };
+        let response = search(
+            &DocumentStore::default(),
+            &input,
+            &SearchSettings::default(),
+        );
+        assert!(
+            matches!(&response, Err(SearchError::Unsupported(reason)) if reason.contains("scroll")),
+            "{:?}",
+            response
+        );
+    }
+
+    #[test]
+    fn aliased_fields_are_searched_under_their_stored_name() {
+        let settings = SearchSettings {
+            field_aliases: FieldAliases::parse(r#"{"message": "Description"}"#).unwrap(),
+            ..SearchSettings::default()
+        };
+        let body = json!({
+            "query": {"multi_match": {"query": "fire", "fields": ["message"]}},
+            "sort": [{"message": "asc"}],
+            "_source": {"includes": ["message"]},
+        })
+        .to_string();
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: body.as_bytes(),
+        };
+        let response = search(&DocumentStore::default(), &input, &settings).unwrap();
+        assert_eq!(response["hits"]["total"]["value"], 1);
+        assert!(descriptions(&response)[0].starts_with("Through the fire"));
+    }
+
+    #[test]
+    fn match_all_returns_the_default_size_unless_given() {
+        let store = DocumentStore::new(
+            (0..50)
+                .map(|i| json!({"Description": format!("lyric {}", i)}))
+                .collect(),
+        );
+        let hits = |body: Value| {
+            let response = emulate(search, &store, "", body);
+            assert_eq!(response["hits"]["total"]["value"], 50);
+            response["hits"]["hits"].as_array().unwrap().len()
+        };
+        assert_eq!(hits(json!({"query": {"match_all": {}}})), DEFAULT_SIZE);
+        assert_eq!(hits(Value::Null), 10);
+        assert_eq!(hits(json!({"query": {"match_all": {}}, "size": 30})), 30);
+        assert_eq!(hits(json!({"size": 0})), 0);
+    }
+
+    #[test]
+    fn query_string_combines_its_terms() {
+        let total = |query: &str| {
+            search_lyrics(
+                json!({"query": {"query_string": {"query": query, "analyze_wildcard": true}}}),
+            )["hits"]["total"]["value"]
+                .clone()
+        };
+        assert_eq!(total("fire"), 1);
+        assert_eq!(total("fire limit"), 1);
+        // OR by default
+        assert_eq!(total("fire saying"), 2);
+        assert_eq!(total("fire OR saying"), 2);
+        assert_eq!(total("fire AND limit"), 1);
+        assert_eq!(total("fire AND saying"), 0);
+        let query =
+            json!({"query": {"query_string": {"query": "fire limit", "default_operator": "AND"}}});
+        assert_eq!(search_lyrics(query)["hits"]["total"]["value"], 1);
+        for query in ["(fire)", "fi*", "Description:fire", "year:[1980 TO 1990]"] {
+            unsupported(json!({"query": {"query_string": {"query": query}}}));
+        }
+    }
+
+    #[test]
+    fn nested_source_round_trips() {
+        let document = json!({
+            "_index": "songs",
+            "_id": "42",
+            "_version": 3,
+            "Description": "Through the fire",
+            "album": {"title": "Stan Bush", "tracks": [1, 2, {"bonus": true}]},
+        });
+        let store = DocumentStore::new(vec![document.clone()]);
+        let response = emulate(search, &store, "version=true", Value::Null);
+        let hit = &response["hits"]["hits"][0];
+        assert_eq!(hit["_index"], "songs");
+        assert_eq!(hit["_id"], "42");
+        assert_eq!(hit["_version"], 3);
+        let mut source = document;
+        for metadata in ["_index", "_id", "_version"] {
+            source.as_object_mut().unwrap().remove(metadata);
+        }
+        assert_eq!(hit["_source"], source);
+
+        let response = emulate(
+            search,
+            &store,
+            "",
+            json!({"_source": {"includes": ["album"]}}),
+        );
+        assert_eq!(
+            response["hits"]["hits"][0]["_source"],
+            json!({"album": source["album"]})
+        );
+    }
+
+    #[test]
+    fn took_is_measured_unless_fixed() {
+        let took = search_lyrics(json!({"query": {"match_all": {}}}))["took"].clone();
+        assert!(took.as_u64().is_some(), "{}", took);
+        let settings = SearchSettings {
+            fixed_took: Some(7),
+            ..SearchSettings::default()
+        };
+        let input = SearchInput {
+            index: None,
+            query: "",
+            body: b"",
+        };
+        let response = search(&DocumentStore::default(), &input, &settings).unwrap();
+        assert_eq!(response["took"], 7);
+    }
+
+    #[test]
+    fn version_is_only_reported_when_asked() {
+        let store = DocumentStore::new(vec![
+            json!({"_version": 3, "Description": "Through the fire"}),
+            json!({"Description": "To the wall"}),
+        ]);
+        let versions = |response: Value| -> Vec<Value> {
+            response["hits"]["hits"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hit| hit.get("_version").cloned().unwrap_or(Value::Null))
+                .collect()
+        };
+        let response = emulate(search, &store, "", json!({"version": true}));
+        assert_eq!(versions(response), [json!(3), json!(DOCUMENT_VERSION)]);
+        let response = emulate(search, &store, "", json!({"version": false}));
+        assert_eq!(versions(response), [Value::Null, Value::Null]);
+        // false by default
+        let response = emulate(search, &store, "", Value::Null);
+        assert_eq!(versions(response), [Value::Null, Value::Null]);
+    }
+
+    #[test]
+    fn wildcard_and_prefix_match_the_tokens() {
+        let total =
+            |query: Value| search_lyrics(json!({"query": query}))["hits"]["total"]["value"].clone();
+        assert_eq!(total(json!({"wildcard": {"Description": "fi*"}})), 1);
+        assert_eq!(total(json!({"wildcard": {"Description": "*fire*"}})), 1);
+        assert_eq!(total(json!({"wildcard": {"Description": "FI?E"}})), 1);
+        // Other regex metacharacters are literal
+        assert_eq!(total(json!({"wildcard": {"Description": "fi.e"}})), 0);
+        assert_eq!(total(json!({"prefix": {"Description": "thr"}})), 2);
+        assert_eq!(
+            total(json!({"prefix": {"Description": {"value": "THR"}}})),
+            2
+        );
+        // Combined with the other filters
+        let query = json!({"bool": {"filter": [
+            {"prefix": {"Description": "thr"}},
+            {"wildcard": {"Description": "*mit"}},
+        ]}});
+        assert_eq!(total(query), 1);
+    }
+
+    #[test]
+    fn typed_keys_takes_a_boolean() {
+        let store = DocumentStore::default();
+        let body = json!({"aggs": {"lyrics": {"value_count": {"field": "Description"}}}});
+        let keys = |query| {
+            let response = emulate(search, &store, query, body.clone());
+            let aggregations = response["aggregations"].as_object().unwrap();
+            aggregations.keys().cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(keys("typed_keys"), ["value_count#lyrics"]);
+        assert_eq!(keys("typed_keys=true"), ["value_count#lyrics"]);
+        assert_eq!(keys("typed_keys=false"), ["lyrics"]);
+        let input = SearchInput {
+            index: None,
+            query: "typed_keys=maybe",
+            body: b"{}",
+        };
+        assert!(matches!(
+            search(&store, &input, &SearchSettings::default()),
+            Err(SearchError::Unsupported(reason)) if reason.contains("typed_keys")
+        ));
+    }
+
+    #[test]
+    fn filter_clauses_are_not_scored() {
+        let fire = json!({"match": {"Description": "fire"}});
+        let filtered = search_lyrics(json!({"query": {"bool": {"filter": [fire]}}}));
+        assert_eq!(scores(&filtered), [CONSTANT_SCORE]);
+        let scored = search_lyrics(json!({"query": {"bool": {"must": [fire]}}}));
+        assert_eq!(descriptions(&scored), descriptions(&filtered));
+        assert_ne!(scores(&scored), [CONSTANT_SCORE]);
+        // Only the must clause is scored next to the filter one
+        let both = search_lyrics(json!({"query": {"bool": {
+            "must": [fire],
+            "filter": [{"match": {"Description": "limit"}}],
+        }}}));
+        assert_eq!(scores(&both), scores(&scored));
+    }
+
+    #[test]
+    fn filter_clauses_are_highlighted() {
+        let response = search_lyrics(json!({
+            "query": {"bool": {"filter": [{"match": {"Description": "limit"}}]}},
+            "highlight": {"fields": {"Description": {}}},
+        }));
+        let highlight = &response["hits"]["hits"][0]["highlight"]["Description"][0];
+        assert!(highlight.as_str().unwrap().contains("<em>limit</em>"));
+    }
+
+    #[test]
+    fn nested_bool_text_clauses_are_scored_and_highlighted() {
+        let should = json!({"bool": {"should": [
+            {"match": {"Description": "fire"}},
+            {"match": {"Description": "saying"}},
+        ]}});
+        let top_level = search_lyrics(json!({"query": should}));
+        let nested = search_lyrics(json!({
+            "query": {"bool": {"must": [should]}},
+            "highlight": {"fields": {"Description": {}}},
+        }));
+        assert_eq!(descriptions(&nested), descriptions(&top_level));
+        assert_eq!(scores(&nested), scores(&top_level));
+        assert_ne!(scores(&nested), [CONSTANT_SCORE; 2]);
+        let highlight = &nested["hits"]["hits"][0]["highlight"]["Description"][0];
+        assert!(highlight.as_str().unwrap().contains("<em>"));
+        // Nothing is scored in filter context, however deep
+        let filtered = search_lyrics(json!({"query": {"bool": {"filter": [
+            {"bool": {"must": [should]}},
+        ]}}}));
+        assert_eq!(scores(&filtered), [CONSTANT_SCORE; 2]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Documents served by the emulated endpoints.
+
+use crate::index::InvertedIndex;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Stores with fewer documents aren't indexed, scanning all of them is fast enough
+const INDEX_MIN_DOCUMENTS: usize = 1000;
+
+/// Generation of the latest store created
+static LATEST_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Documents (their `_source`) of the emulated index, in insertion order
+pub struct DocumentStore {
+    documents: Vec<Value>,
+    // Built along with the documents, so it's never out of date
+    index: Option<InvertedIndex>,
+    generation: u64,
+}
+
+/// Metadata of an index, collected from the documents naming it in their `_index` field
+#[derive(Debug, PartialEq)]
+pub struct IndexMetadata {
+    pub name: String,
+    pub docs_count: usize,
+    /// Size of the documents serialized as JSON
+    pub size_in_bytes: usize,
+}
+
+impl DocumentStore {
+    /// Load the documents from a JSON file containing an array of objects.
+    /// Their `_index`, `_id` and `_version` fields are reported as metadata of the hits.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read(path)
+            .map_err(|err| format!("can't read documents file {}: {}", path.display(), err))?;
+        let documents: Value = serde_json::from_slice(&content).map_err(|err| {
+            format!(
+                "can't parse documents file {} as JSON: {}",
+                path.display(),
+                err
+            )
+        })?;
+        let Value::Array(documents) = documents else {
+            return Err(format!(
+                "expected JSON array of documents in {}",
+                path.display()
+            ));
+        };
+        if let Some(position) = documents.iter().position(|document| !document.is_object()) {
+            return Err(format!(
+                "expected JSON object as document {} in {}",
+                position,
+                path.display()
+            ));
+        }
+        Ok(DocumentStore::new(documents))
+    }
+
+    /// Store of the documents, indexed unless there are only a few of them
+    pub fn new(documents: Vec<Value>) -> Self {
+        let index =
+            (documents.len() >= INDEX_MIN_DOCUMENTS).then(|| InvertedIndex::build(&documents));
+        DocumentStore {
+            documents,
+            index,
+            generation: next_generation(),
+        }
+    }
+
+    /// Store of the documents without any index, always scanned in full
+    pub fn unindexed(documents: Vec<Value>) -> Self {
+        DocumentStore {
+            documents,
+            index: None,
+            generation: next_generation(),
+        }
+    }
+
+    pub fn documents(&self) -> &[Value] {
+        &self.documents
+    }
+
+    pub(crate) fn index(&self) -> Option<&InvertedIndex> {
+        self.index.as_ref()
+    }
+
+    /// Number telling this store apart from the other ones created by the process,
+    /// e.g. for results computed from a previous one to be thrown away
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Indices named by the `_index` field of the documents, sorted by name.
+    /// Empty when none of the documents has one, like the built-in lyrics.
+    pub fn indices(&self) -> Vec<IndexMetadata> {
+        let mut indices: BTreeMap<&str, IndexMetadata> = BTreeMap::new();
+        for document in &self.documents {
+            let Some(name) = document.get("_index").and_then(Value::as_str) else {
+                continue;
+            };
+            let index = indices.entry(name).or_insert_with(|| IndexMetadata {
+                name: name.to_string(),
+                docs_count: 0,
+                size_in_bytes: 0,
+            });
+            index.docs_count += 1;
+            index.size_in_bytes += document.to_string().len();
+        }
+        indices.into_values().collect()
+    }
+}
+
+/// The lyrics, served when no documents file is configured.
+impl Default for DocumentStore {
+    fn default() -> Self {
+        DocumentStore::new(vec![
+            json!({"Description": "Through the fire, to the limit, to the wall, For a chance to be with you, I'd gladly risk it all."}),
+            json!({"Description": "You tell me you're gonna play it smart, We're through before we start, But I believe that we've only just begun"}),
+            json!({"Description": "When it's this good, there's no saying no"}),
+        ])
+    }
+}
+
+fn next_generation() -> u64 {
+    LATEST_GENERATION.fetch_add(1, Ordering::Relaxed) + 1
+}
@@ -0,0 +1,114 @@
+//! TLS towards OpenSearch, for clusters that only accept HTTPS.
+//!
+//! Certificate problems make the TLS handshake fail while connecting, so the forwarded
+//! request gets a 502 (`upstream_unavailable`) with the rustls error as the reason:
+//! - hostname mismatch: `invalid peer certificate: certificate not valid for name ...`
+//!   (set `--upstream-tls-server-name` when the certificate isn't issued for the upstream IP)
+//! - expired certificate: `invalid peer certificate: certificate expired ...`
+//! - unknown CA (like a self-signed certificate): `invalid peer certificate: UnknownIssuer`
+//! - plaintext OpenSearch: `received corrupt message ...`
+//!
+//! A handshake that doesn't complete in time gets a 504 like any other slow response.
+
+use hyper_rustls::ConfigBuilderExt;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+/// TLS settings for connecting to OpenSearch.
+/// Trusts the CA certificates from `ca_cert` if given, else the system ones.
+/// With `insecure`, any certificate is accepted, only meant for self-signed dev clusters.
+pub fn client_config(ca_cert: Option<&Path>, insecure: bool) -> Result<ClientConfig, String> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+    let config = if insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(
+                ring::default_provider(),
+            )))
+            .with_no_client_auth()
+    } else if let Some(ca_cert) = ca_cert {
+        builder
+            .with_root_certificates(load_ca_certs(ca_cert)?)
+            .with_no_client_auth()
+    } else {
+        builder
+            .with_native_roots()
+            .map_err(|err| format!("can't load system CA certificates: {}", err))?
+            .with_no_client_auth()
+    };
+    Ok(config)
+}
+
+fn load_ca_certs(path: &Path) -> Result<RootCertStore, String> {
+    let file = File::open(path)
+        .map_err(|err| format!("can't read CA certificate {}: {}", path.display(), err))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(file)) {
+        let cert =
+            cert.map_err(|err| format!("can't parse CA certificate {}: {}", path.display(), err))?;
+        roots
+            .add(cert)
+            .map_err(|err| format!("invalid CA certificate {}: {}", path.display(), err))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("no CA certificate found in {}", path.display()));
+    }
+    Ok(roots)
+}
+
+/// Skips the certificate checks, but still verifies the handshake signatures
+#[derive(Debug)]
+struct AcceptAnyCertificate(CryptoProvider);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,72 @@
+//! OpenSearch nodes the forwarded requests are spread over, round-robin.
+//! A node failing to accept connections is skipped for a while, the others taking its requests.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct Upstreams {
+    nodes: Vec<Node>,
+    next: AtomicUsize,
+    /// How long a node failing to connect is skipped
+    skip: Duration,
+}
+
+pub struct Node {
+    pub addr: SocketAddr,
+    /// Base URL of the node, forwarded request paths are appended to it.
+    /// The client pools the connections per host, so each node has its own.
+    pub url: String,
+    skipped_until: Mutex<Option<Instant>>,
+}
+
+impl Upstreams {
+    pub fn new(addrs: &[SocketAddr], tls: bool, skip: Duration) -> Self {
+        let scheme = if tls { "https" } else { "http" };
+        let nodes = addrs
+            .iter()
+            .map(|addr| Node {
+                addr: *addr,
+                url: format!("{}://{}", scheme, addr),
+                skipped_until: Mutex::new(None),
+            })
+            .collect();
+        Upstreams {
+            nodes,
+            next: AtomicUsize::new(0),
+            skip,
+        }
+    }
+
+    /// Next node in turn, passing over the ones which recently failed to connect.
+    /// They still take their turn, so the requests are spread evenly over the others.
+    /// When they all failed, the next one is tried anyway.
+    pub fn pick(&self) -> &Node {
+        let now = Instant::now();
+        let mut first = None;
+        for _ in 0..self.nodes.len() {
+            let node = &self.nodes[self.next.fetch_add(1, Ordering::Relaxed) % self.nodes.len()];
+            let skipped_until = *node.skipped_until.lock().unwrap();
+            if skipped_until.is_none_or(|until| now >= until) {
+                return node;
+            }
+            first.get_or_insert(node);
+        }
+        first.expect("at least one upstream")
+    }
+
+    /// The node couldn't be connected to, the next requests go to the other ones
+    pub fn record_failure(&self, node: &Node) {
+        *node.skipped_until.lock().unwrap() = Some(Instant::now() + self.skip);
+    }
+
+    /// The node was connected to, it's back if it was skipped
+    pub fn record_success(&self, node: &Node) {
+        *node.skipped_until.lock().unwrap() = None;
+    }
+
+    pub fn urls(&self) -> Vec<&str> {
+        self.nodes.iter().map(|node| node.url.as_str()).collect()
+    }
+}